@@ -1,4 +1,4 @@
-use pyo3::exceptions::PyTypeError;
+use pyo3::exceptions::{PyRuntimeError, PyTypeError};
 use pyo3::prelude::*;
 
 /// Formats the sum of two numbers as string.
@@ -9,10 +9,15 @@ fn sum_as_string(a: usize, b: usize) -> PyResult<String> {
 
 #[pyfunction]
 pub fn sq_exec(sql: &str, output: Option<&str>) -> PyResult<String> {
-    let rt = tokio::runtime::Runtime::new().unwrap();
-    let mut ds = rt.block_on(async { sq::execute(sql).await.unwrap() });
+    let rt = tokio::runtime::Runtime::new()?;
+    let mut ds = rt
+        .block_on(async { sq::execute(sql).await })
+        .map_err(|e| match e.diagnostic() {
+            Some(d) => PyRuntimeError::new_err(d.render(sql)),
+            None => PyRuntimeError::new_err(e.to_string()),
+        })?;
     match output {
-        Some("csv") | None => Ok(ds.to_csv().unwrap()),
+        Some("csv") | None => ds.to_csv().map_err(|e| PyRuntimeError::new_err(e.to_string())),
         Some(v) => Err(PyTypeError::new_err(format!("type {} not supported", v))),
     }
 }
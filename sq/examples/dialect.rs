@@ -36,7 +36,7 @@ impl Default for MyDialect {
 impl MyDialect {
     pub fn new() -> MyDialect {
         MyDialect {
-            g: dialect::GenericDialect::default(),
+            g: dialect::GenericDialect,
         }
     }
 }
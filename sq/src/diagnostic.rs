@@ -0,0 +1,169 @@
+use std::fmt;
+
+/// 1-based position of a fragment in the sql
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+impl Span {
+    /// find `fragment` in `sql` ignoring case, preferably as a whole word. the
+    /// fragment may be formatted from the AST and differ from the sql in spacing,
+    /// in which case it's not found rather than guessed
+    pub(crate) fn find(sql: &str, fragment: &str) -> Option<Span> {
+        let haystack = sql.to_ascii_lowercase();
+        let fragment = fragment.to_ascii_lowercase();
+        let offset = find_word(&haystack, &fragment).or_else(|| haystack.find(&fragment))?;
+
+        let before = &sql[..offset];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().unwrap_or_default().chars().count() + 1;
+        Some(Span { line, column })
+    }
+}
+
+fn find_word(haystack: &str, word: &str) -> Option<usize> {
+    let is_ident = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+    haystack.match_indices(word).map(|(i, _)| i).find(|&i| {
+        !is_ident(haystack[..i].chars().next_back()) && !is_ident(haystack[i + word.len()..].chars().next())
+    })
+}
+
+/// an error in a query pointing at the offending fragment of the sql, with
+/// suggestions to fix it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub fragment: String,
+    pub span: Option<Span>,
+    pub suggestions: Vec<String>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(span) = self.span {
+            write!(f, " at {}", span)?;
+        }
+        if !self.suggestions.is_empty() {
+            write!(f, ", did you mean {}?", self.suggestions.join(" or "))?;
+        }
+        Ok(())
+    }
+}
+
+impl Diagnostic {
+    /// an unknown column `name`, suggesting the closest of `candidates`
+    pub(crate) fn column_not_found<I: IntoIterator<Item = String>>(name: &str, candidates: I) -> Self {
+        Diagnostic {
+            message: format!("column {} not found", name),
+            fragment: name.to_owned(),
+            span: None,
+            suggestions: suggest(name, candidates),
+        }
+    }
+
+    /// an unknown relation `name`, suggesting the closest of `candidates`
+    pub(crate) fn relation_not_found<I: IntoIterator<Item = String>>(name: &str, candidates: I) -> Self {
+        Diagnostic {
+            message: format!("relation {} does not exist", name),
+            fragment: name.to_owned(),
+            span: None,
+            suggestions: suggest(name, candidates),
+        }
+    }
+
+    /// render the line of `sql` the diagnostic points at, with the fragment
+    /// underlined by carets
+    pub fn render(&self, sql: &str) -> String {
+        let mut res = format!("error: {}\n", self.message);
+        if let Some(Span { line, column }) = self.span {
+            let src = sql.lines().nth(line - 1).unwrap_or_default();
+            let gutter = " ".repeat(line.to_string().len());
+            let width = self.fragment.chars().count().max(1);
+            res += &format!("{} --> line {}, column {}\n", gutter, line, column);
+            res += &format!("{} |\n", gutter);
+            res += &format!("{} | {}\n", line, src);
+            res += &format!("{} | {}{}\n", gutter, " ".repeat(column - 1), "^".repeat(width));
+        }
+        if !self.suggestions.is_empty() {
+            res += &format!("help: did you mean {}?\n", self.suggestions.join(" or "));
+        }
+        res
+    }
+}
+
+/// candidates within an edit distance of a third of `name`, closest first
+fn suggest<I: IntoIterator<Item = String>>(name: &str, candidates: I) -> Vec<String> {
+    let max = (name.chars().count() / 3).max(1);
+    let mut found = candidates
+        .into_iter()
+        .map(|c| (edit_distance(&name.to_lowercase(), &c.to_lowercase()), c))
+        .filter(|(d, _)| *d <= max)
+        .collect::<Vec<_>>();
+    found.sort();
+    found.dedup_by(|a, b| a.1 == b.1);
+    found.into_iter().map(|(_, c)| c).take(3).collect()
+}
+
+/// levenshtein distance between `a` and `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            cur[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suggest() {
+        assert_eq!(edit_distance("totl_cases", "total_cases"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+        let candidates = ["location", "total_cases", "new_cases", "continent"].map(str::to_owned);
+        assert_eq!(suggest("totl_cases", candidates.clone()), ["total_cases"]);
+        assert_eq!(suggest("new_case", candidates.clone()), ["new_cases"]);
+        assert!(suggest("population", candidates).is_empty());
+    }
+
+    #[test]
+    fn test_find() {
+        let sql = "select new_cases, cases\nfrom file:///tmp/covid.csv";
+        assert_eq!(Span::find(sql, "CASES"), Some(Span { line: 1, column: 19 }));
+        assert_eq!(Span::find(sql, "covid"), Some(Span { line: 2, column: 18 }));
+        // formatted differently from the sql, so not found instead of guessed
+        assert_eq!(Span::find(sql, "cases  FROM"), None);
+    }
+
+    #[test]
+    fn test_render() {
+        let sql = "select location,\n       totl_cases\nfrom file:///tmp/covid.csv";
+        let mut d = Diagnostic::column_not_found("totl_cases", ["total_cases".to_owned()]);
+        d.span = Span::find(sql, &d.fragment);
+        assert_eq!(d.span, Some(Span { line: 2, column: 8 }));
+        assert_eq!(d.render(sql), "\
+error: column totl_cases not found
+  --> line 2, column 8
+  |
+2 |        totl_cases
+  |        ^^^^^^^^^^
+help: did you mean total_cases?
+");
+    }
+}
@@ -11,6 +11,12 @@ pub struct FetchData {
 #[async_trait]
 pub trait Fetch {
     async fn fetch(&self, data: &str) -> Result<FetchData, SqError>;
+
+    /// name of the fetcher reported by EXPLAIN, its type name by default
+    fn name(&self) -> &'static str {
+        let name = std::any::type_name::<Self>();
+        name.rsplit("::").next().unwrap_or(name)
+    }
 }
 
 #[derive(Debug)]
@@ -39,7 +45,7 @@ struct FileFetcher;
 #[async_trait]
 impl Fetch for FileFetcher {
     async fn fetch(&self, data: &str) -> Result<FetchData, SqError> {
-        let url = &data[7..];
+        let url = data.strip_prefix("file://").ok_or_else(|| SqError::unsupported(data))?;
         let hint = if url.ends_with(".csv") {
             Some("csv".to_owned())
         } else if url.ends_with(".parquet") {
@@ -60,10 +66,13 @@ struct CommandFetcher;
 #[async_trait]
 impl Fetch for CommandFetcher {
     async fn fetch(&self, data: &str) -> Result<FetchData, SqError> {
-        let cmd = &data[6..];
+        let cmd = data.strip_prefix("cmd://").ok_or_else(|| SqError::unsupported(data))?;
         let (cmd, args) = {
             let mut parts = cmd.split_terminator('?');
-            (parts.next().unwrap(), parts.next().map(|s| vec![s]).unwrap_or(vec![]))
+            match parts.next() {
+                Some(cmd) if !cmd.is_empty() => (cmd, parts.next().map(|s| vec![s]).unwrap_or(vec![])),
+                _ => return Err(SqError::unsupported(data)),
+            }
         };
 
         let output = Command::new(cmd).args(args).output()?;
@@ -76,11 +85,16 @@ impl Fetch for CommandFetcher {
 
 pub async fn fetch<S: AsRef<str>>(s: S) -> Result<FetchData, SqError> {
     let url = s.as_ref();
-    let f: Box<dyn Fetch> = match &url[0..4] {
-        "http" => Box::new(HttpFetcher),
-        "file" => Box::new(FileFetcher),
-        "cmd:" => Box::new(CommandFetcher),
-        _ => todo!("unsupported url"),
-    };
-    f.fetch(url).await
+    fetcher(url)?.fetch(url).await
+}
+
+/// the fetcher of a url, chosen by its scheme
+pub(crate) fn fetcher(url: &str) -> Result<Box<dyn Fetch>, SqError> {
+    let scheme = url.split_once("://").map(|(scheme, _)| scheme);
+    Ok(match scheme {
+        Some("http" | "https") => Box::new(HttpFetcher),
+        Some("file") => Box::new(FileFetcher),
+        Some("cmd") => Box::new(CommandFetcher),
+        _ => return Err(SqError::unsupported(url)),
+    })
 }
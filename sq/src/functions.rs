@@ -0,0 +1,595 @@
+use std::f64::consts::E;
+
+use polars::prelude::*;
+
+use super::SqError;
+
+/// a literal argument of a function, which polars requires to be known when
+/// building the expression
+fn literal<'a>(name: &str, e: &'a Expr) -> Result<&'a LiteralValue, SqError> {
+    match e {
+        Expr::Literal(v) => Ok(v),
+        _ => Err(SqError::AstError(format!("argument {} of {} must be a literal", e, name))),
+    }
+}
+
+fn literal_str<'a>(name: &str, e: &'a Expr) -> Result<&'a str, SqError> {
+    match literal(name, e)? {
+        LiteralValue::Utf8(s) => Ok(s.as_str()),
+        _ => Err(SqError::AstError(format!("argument {} of {} must be a string", e, name))),
+    }
+}
+
+pub(crate) fn literal_int(name: &str, e: &Expr) -> Result<i64, SqError> {
+    let n = match literal(name, e)? {
+        LiteralValue::Int32(n) => Some(*n as i64),
+        LiteralValue::Int64(n) => Some(*n),
+        LiteralValue::UInt32(n) => Some(*n as i64),
+        LiteralValue::UInt64(n) => Some(*n as i64),
+        LiteralValue::Float64(f) if f.fract() == 0.0 => Some(*f as i64),
+        _ => None,
+    };
+    n.ok_or_else(|| SqError::AstError(format!("argument {} of {} must be an integer", e, name)))
+}
+
+/// map a string column by `f`, keeping nulls
+fn map_utf8(e: Expr, f: impl Fn(&str) -> String + Send + Sync + 'static) -> Expr {
+    e.map(
+        move |s| Ok(s.utf8()?.into_iter().map(|v| v.map(&f)).collect::<Utf8Chunked>().into_series()),
+        GetOutput::from_type(DataType::Utf8),
+    )
+}
+
+/// map a column by `f` if it's of a float type, other numbers are kept as is
+fn map_float(e: Expr, f: impl Fn(&Series) -> PolarsResult<Series> + Send + Sync + 'static) -> Expr {
+    e.map(
+        move |s| if s.dtype().is_float() { f(&s) } else { Ok(s) },
+        GetOutput::same_type(),
+    )
+}
+
+/// cast `e` to `dtype`, a strict cast fails on values that can't be converted
+/// instead of turning them into NULLs. polars doesn't parse strings when casting
+/// to temporal types, so they are parsed here
+pub(crate) fn cast(e: Expr, dtype: DataType, strict: bool) -> Expr {
+    if !matches!(dtype, DataType::Date | DataType::Datetime(..)) {
+        return if strict { e.strict_cast(dtype) } else { e.cast(dtype) };
+    }
+
+    let output = GetOutput::from_type(dtype.clone());
+    e.map(
+        move |s| {
+            if s.dtype() != &DataType::Utf8 {
+                return if strict { s.strict_cast(&dtype) } else { s.cast(&dtype) };
+            }
+            let ca = s.utf8()?;
+            if ca.null_count() == ca.len() {
+                return Ok(Series::full_null(s.name(), s.len(), &dtype));
+            }
+            let res = match dtype {
+                DataType::Datetime(tu, _) => ca.as_datetime(None, tu).map(|ca| ca.into_series()),
+                _ => ca.as_date(None).map(|ca| ca.into_series()),
+            };
+            match (res, strict) {
+                (Ok(res), false) => Ok(res),
+                (Err(_), false) => Ok(Series::full_null(s.name(), s.len(), &dtype)),
+                (Ok(res), true) if res.null_count() == s.null_count() => Ok(res),
+                _ => Err(PolarsError::ComputeError(format!("conversion from {} to {} failed", s.name(), dtype).into())),
+            }
+        },
+        output,
+    )
+}
+
+/// a literal of `dtype` written as a string like `DATE '2022-01-01'`, temporal
+/// values are parsed when building the expression so invalid ones fail early
+pub(crate) fn typed_literal(dtype: DataType, value: &str) -> Result<Expr, SqError> {
+    use polars::export::chrono::{NaiveDate, NaiveDateTime};
+
+    let invalid = || SqError::AstError(format!("{} '{}' is invalid", dtype, value));
+    match dtype {
+        DataType::Date => {
+            let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| invalid())?;
+            let dt = date.and_hms_opt(0, 0, 0).ok_or_else(invalid)?;
+            Ok(Expr::Literal(LiteralValue::DateTime(dt, TimeUnit::Milliseconds)).cast(DataType::Date))
+        }
+        DataType::Datetime(tu, _) => {
+            let dt = ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M"]
+                .iter()
+                .find_map(|fmt| NaiveDateTime::parse_from_str(value, fmt).ok())
+                .or_else(|| NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?.and_hms_opt(0, 0, 0))
+                .ok_or_else(invalid)?;
+            Ok(Expr::Literal(LiteralValue::DateTime(dt, tu)))
+        }
+        dtype => Ok(cast(lit(value), dtype, true)),
+    }
+}
+
+/// an interval like `INTERVAL '1 day 2 hours'` or `INTERVAL '3' HOUR`, months
+/// and years have no fixed duration so aren't supported
+pub(crate) fn interval(value: &str, unit: Option<&str>) -> Result<LiteralValue, SqError> {
+    use polars::export::chrono::Duration;
+
+    let invalid = || SqError::AstError(format!("INTERVAL '{}' is invalid", value));
+    let parts = value.split_whitespace().collect::<Vec<_>>();
+    let pairs = match unit {
+        Some(unit) if parts.len() == 1 => vec![(parts[0], unit)],
+        Some(_) => return Err(invalid()),
+        None if parts.len() % 2 == 0 => parts.chunks(2).map(|p| (p[0], p[1])).collect(),
+        None => return Err(invalid()),
+    };
+
+    let mut res = Duration::zero();
+    for (n, unit) in pairs {
+        let n = n.parse::<i64>().map_err(|_| invalid())?;
+        let unit = unit.to_lowercase();
+        let duration = match unit.trim_end_matches('s') {
+            "microsecond" => Duration::microseconds(n),
+            "millisecond" => Duration::milliseconds(n),
+            "second" | "sec" => Duration::seconds(n),
+            "minute" | "min" => Duration::minutes(n),
+            "hour" => Duration::hours(n),
+            "day" => Duration::days(n),
+            "week" => Duration::weeks(n),
+            _ => return Err(SqError::unsupported(format!("INTERVAL {} {}", n, unit))),
+        };
+        res = res.checked_add(&duration).ok_or_else(invalid)?;
+    }
+    Ok(LiteralValue::Duration(res, TimeUnit::Microseconds))
+}
+
+/// match strings against a regex, the pattern must be a literal
+pub(crate) fn regex_match(e: Expr, pattern: &Expr, case_insensitive: bool) -> Result<Expr, SqError> {
+    let pattern = literal_str("regex match", pattern)?;
+    regex::Regex::new(pattern).map_err(|err| SqError::AstError(format!("invalid regex {}: {}", pattern, err)))?;
+    Ok(if case_insensitive {
+        e.str().contains(format!("(?i){}", pattern))
+    } else {
+        e.str().contains(pattern)
+    })
+}
+
+/// match strings against a LIKE pattern, where `%` matches any sequence of
+/// characters and `_` matches any single character
+pub(crate) fn like(e: Expr, pattern: &Expr, escape: Option<char>, case_insensitive: bool) -> Result<Expr, SqError> {
+    let escape = escape.unwrap_or('\\');
+    let mut re = String::from("^(?s)");
+    let mut chars = literal_str("LIKE", pattern)?.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c == escape => match chars.next() {
+                Some(c) => re.push_str(&regex::escape(&c.to_string())),
+                None => return Err(SqError::AstError(format!("LIKE pattern {} must not end with escape character", pattern))),
+            },
+            '%' => re.push_str(".*"),
+            '_' => re.push('.'),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    regex_match(e, &lit(re), case_insensitive)
+}
+
+fn check_arity(name: &str, args: &[Expr], n: std::ops::RangeInclusive<usize>) -> Result<(), SqError> {
+    if n.contains(&args.len()) {
+        Ok(())
+    } else {
+        Err(SqError::AstError(format!("{} takes {:?} arguments, got {}", name, n, args.len())))
+    }
+}
+
+/// build the expression of a scalar function by its (lower cased) name
+pub(crate) fn scalar(name: &str, args: Vec<Expr>) -> Result<Expr, SqError> {
+    let arity = |n| check_arity(name, &args, n);
+
+    match name {
+        // string functions
+        "upper" | "lower" | "length" | "char_length" => {
+            arity(1..=1)?;
+            let e = args[0].clone();
+            Ok(match name {
+                "upper" => e.str().to_uppercase(),
+                "lower" => e.str().to_lowercase(),
+                _ => e.map(
+                    |s| Ok(s.utf8()?.str_n_chars().into_series()),
+                    GetOutput::from_type(DataType::UInt32),
+                ),
+            })
+        }
+        "trim" | "ltrim" | "rtrim" => {
+            arity(1..=2)?;
+            // only a single character can be trimmed other than whitespaces
+            let matches = match args.get(1) {
+                Some(e) => {
+                    let mut chars = literal_str(name, e)?.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(c), None) => Some(c),
+                        _ => return Err(SqError::AstError(format!("argument {} of {} must be a single character", e, name))),
+                    }
+                }
+                None => None,
+            };
+            let e = args[0].clone().str();
+            Ok(match name {
+                "trim" => e.strip(matches),
+                "ltrim" => e.lstrip(matches),
+                _ => e.rstrip(matches),
+            })
+        }
+        "substr" | "substring" => {
+            arity(2..=3)?;
+            // positions are 1-based in SQL
+            let start = literal_int(name, &args[1])?.max(1) - 1;
+            let length = args.get(2).map(|e| literal_int(name, e)).transpose()?.map(|n| n.max(0) as u64);
+            Ok(args[0].clone().map(
+                move |s| Ok(s.utf8()?.str_slice(start, length)?.into_series()),
+                GetOutput::from_type(DataType::Utf8),
+            ))
+        }
+        "replace" => {
+            arity(3..=3)?;
+            let from = literal_str(name, &args[1])?.to_owned();
+            let to = literal_str(name, &args[2])?.to_owned();
+            Ok(map_utf8(args[0].clone(), move |v| v.replace(&from, &to)))
+        }
+        "concat" => {
+            arity(1..=usize::MAX)?;
+            // NULL arguments are ignored
+            let args = args.into_iter().map(|e| e.cast(DataType::Utf8).fill_null(lit(""))).collect::<Vec<_>>();
+            Ok(concat_str(args, ""))
+        }
+        "split_part" => {
+            arity(3..=3)?;
+            let delimiter = literal_str(name, &args[1])?.to_owned();
+            let n = literal_int(name, &args[2])?;
+            if n < 1 {
+                return Err(SqError::AstError(format!("field position of {} must be greater than zero", name)));
+            }
+            Ok(map_utf8(args[0].clone(), move |v| {
+                v.split(delimiter.as_str()).nth(n as usize - 1).unwrap_or_default().to_owned()
+            }))
+        }
+        "starts_with" => {
+            arity(2..=2)?;
+            Ok(args[0].clone().str().starts_with(literal_str(name, &args[1])?))
+        }
+
+        // math functions
+        "abs" | "floor" | "ceil" | "ceiling" | "sqrt" | "ln" | "log10" => {
+            arity(1..=1)?;
+            let e = args[0].clone();
+            Ok(match name {
+                "abs" => e.abs(),
+                "floor" => map_float(e, |s| s.floor()),
+                "ceil" | "ceiling" => map_float(e, |s| s.ceil()),
+                "sqrt" => e.cast(DataType::Float64).pow(0.5),
+                "ln" => e.log(E),
+                _ => e.log(10.0),
+            })
+        }
+        "round" => {
+            arity(1..=2)?;
+            let decimals = args.get(1).map(|e| literal_int(name, e)).transpose()?.unwrap_or(0);
+            let decimals = u32::try_from(decimals)
+                .map_err(|_| SqError::AstError(format!("decimals of {} must not be negative", name)))?;
+            Ok(map_float(args[0].clone(), move |s| s.round(decimals)))
+        }
+        "power" | "pow" => {
+            arity(2..=2)?;
+            Ok(args[0].clone().cast(DataType::Float64).pow(args[1].clone()))
+        }
+
+        // null handling
+        "coalesce" => {
+            arity(1..=usize::MAX)?;
+            Ok(coalesce(&args))
+        }
+        "ifnull" => {
+            arity(2..=2)?;
+            Ok(args[0].clone().fill_null(args[1].clone()))
+        }
+        "nullif" => {
+            arity(2..=2)?;
+            let e = args[0].clone();
+            Ok(when(e.clone().eq(args[1].clone())).then(lit(NULL)).otherwise(e))
+        }
+
+        // conditional
+        "greatest" => {
+            arity(1..=usize::MAX)?;
+            Ok(max_exprs(args))
+        }
+        "least" => {
+            arity(1..=usize::MAX)?;
+            Ok(min_exprs(args))
+        }
+
+        // date and time functions
+        "to_date" | "to_timestamp" | "strptime" => {
+            arity(if name == "strptime" { 2..=2 } else { 1..=2 })?;
+            let fmt = args.get(1).map(|e| literal_str(name, e)).transpose()?.map(str::to_owned);
+            let date_dtype = match name {
+                "to_date" => DataType::Date,
+                _ => DataType::Datetime(TimeUnit::Microseconds, None),
+            };
+            Ok(args[0].clone().str().strptime(StrpTimeOptions { date_dtype, fmt, strict: false, exact: true }))
+        }
+        "now" | "current_timestamp" | "localtimestamp" | "current_date" => {
+            arity(0..=0)?;
+            let now = col(NOW_PLACEHOLDER);
+            Ok(if name == "current_date" { now.cast(DataType::Date) } else { now })
+        }
+        "date_part" => {
+            arity(2..=2)?;
+            date_part(literal_str(name, &args[0])?, args[1].clone())
+        }
+        "date_trunc" => {
+            arity(2..=2)?;
+            // weeks start on monday, while polars counts them from the epoch, a thursday
+            let (every, offset) = match literal_str(name, &args[0])?.to_lowercase().as_str() {
+                "year" => ("1y", "0ns"),
+                "quarter" => ("3mo", "0ns"),
+                "month" => ("1mo", "0ns"),
+                "week" => ("1w", "-3d"),
+                "day" => ("1d", "0ns"),
+                "hour" => ("1h", "0ns"),
+                "minute" => ("1m", "0ns"),
+                "second" => ("1s", "0ns"),
+                unit => return Err(SqError::unsupported(unit)),
+            };
+            Ok(args[1].clone().dt().truncate(every, offset))
+        }
+        "convert_timezone" => {
+            arity(2..=2)?;
+            convert_time_zone(args[1].clone(), literal_str(name, &args[0])?, true)
+        }
+        _ => Err(SqError::unsupported(name)),
+    }
+}
+
+/// a field of dates or timestamps as `EXTRACT(field FROM e)`
+pub(crate) fn date_part(field: &str, e: Expr) -> Result<Expr, SqError> {
+    let dt = e.clone().dt();
+    Ok(match field.to_lowercase().as_str() {
+        "year" => dt.year(),
+        "isoyear" => dt.iso_year(),
+        "quarter" => dt.quarter(),
+        "month" => dt.month(),
+        "week" => dt.week(),
+        "day" => dt.day(),
+        "doy" => dt.ordinal_day(),
+        // polars counts weekdays from monday as 0, sql from sunday as 0 or monday
+        // as 1 for ISO
+        "dow" => (dt.weekday() + lit(1u32)) % lit(7u32),
+        "isodow" => dt.weekday() + lit(1u32),
+        "hour" => dt.hour(),
+        "minute" => dt.minute(),
+        "second" => dt.second(),
+        "millisecond" | "milliseconds" => dt.millisecond(),
+        "microsecond" | "microseconds" => dt.microsecond(),
+        "epoch" => {
+            let ms = cast(e, DataType::Datetime(TimeUnit::Milliseconds, None), true).dt().timestamp(TimeUnit::Milliseconds);
+            ms.cast(DataType::Float64) / lit(1000.0)
+        }
+        field => return Err(SqError::unsupported(field)),
+    })
+}
+
+/// the UTC time of timestamps taken as the local time of `tz` like PostgreSQL
+/// does, where `tz` is a name of the time zone database like `Europe/Paris` or
+/// a UTC offset like `+08:00` (east of Greenwich)
+pub(crate) fn at_time_zone(e: Expr, tz: &str) -> Result<Expr, SqError> {
+    convert_time_zone(e, tz, false)
+}
+
+/// the local time of `tz` at timestamps in UTC if `to_local`, or else the UTC
+/// time of timestamps in the local time of `tz`
+fn convert_time_zone(e: Expr, tz: &str, to_local: bool) -> Result<Expr, SqError> {
+    use polars::export::chrono::Duration;
+
+    let e = cast(e, DataType::Datetime(TimeUnit::Microseconds, None), true);
+    if let Ok(zone) = tz.parse::<chrono_tz::Tz>() {
+        if to_local {
+            let utc = e.dt().with_time_zone(Some("UTC".to_string()));
+            return Ok(utc.dt().cast_time_zone(zone.name().to_string()).dt().with_time_zone(None));
+        }
+        // polars panics on local times skipped by daylight saving changes
+        return Ok(e.map(
+            move |s| {
+                let mut ca: Int64Chunked = s.datetime()?.into_iter().map(|t| t.and_then(|t| local_to_utc(t, zone))).collect();
+                ca.rename(s.name());
+                Ok(ca.into_datetime(TimeUnit::Microseconds, None).into_series())
+            },
+            GetOutput::same_type(),
+        ));
+    }
+    let invalid = || SqError::unsupported(format!("AT TIME ZONE '{}'", tz));
+    let offset = match tz.to_uppercase().as_str() {
+        "UTC" | "GMT" | "Z" => Duration::zero(),
+        _ => {
+            let (sign, hm) = match (tz.strip_prefix('+'), tz.strip_prefix('-')) {
+                (Some(hm), _) => (1, hm),
+                (_, Some(hm)) => (-1, hm),
+                _ => return Err(invalid()),
+            };
+            let (h, m) = match hm.split_once(':') {
+                Some((h, m)) => (h, m),
+                None if hm.len() > 2 => hm.split_at(2),
+                None => (hm, "0"),
+            };
+            let h = h.parse::<i64>().map_err(|_| invalid())?;
+            let m = m.parse::<i64>().map_err(|_| invalid())?;
+            if h > 14 || m > 59 {
+                return Err(invalid());
+            }
+            Duration::minutes(sign * (h * 60 + m))
+        }
+    };
+    let offset = Expr::Literal(LiteralValue::Duration(offset, TimeUnit::Microseconds));
+    Ok(if to_local { e + offset } else { e - offset })
+}
+
+/// the UTC time of `t` microseconds in the local time of `zone`, where a local
+/// time skipped or repeated by a daylight saving change is taken as standard
+/// time like PostgreSQL does
+fn local_to_utc(t: i64, zone: chrono_tz::Tz) -> Option<i64> {
+    use polars::export::chrono::{Duration, LocalResult, NaiveDateTime, Offset, TimeZone};
+
+    let t = NaiveDateTime::from_timestamp_opt(t.div_euclid(1_000_000), t.rem_euclid(1_000_000) as u32 * 1000)?;
+    let utc = match zone.from_local_datetime(&t) {
+        LocalResult::Single(d) | LocalResult::Ambiguous(_, d) => d.naive_utc(),
+        LocalResult::None => t - zone.offset_from_utc_datetime(&(t - Duration::days(1))).fix(),
+    };
+    Some(utc.timestamp_micros())
+}
+
+/// `now()` and the like refer to a column named `NOW_PLACEHOLDER` until the
+/// query is planned, so a query prepared (or a view created) once gets the time
+/// it's run at
+pub(crate) const NOW_PLACEHOLDER: &str = "__sq_now";
+
+/// column of row numbers while evaluating window functions
+pub(crate) const WINDOW_ROW: &str = "__sq_window_row";
+
+/// rows of a partition a window function is evaluated over, relative to the
+/// current row
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Frame {
+    /// all the rows of the partition
+    Partition,
+    /// from the first row to the current row
+    Running,
+    /// from n rows before to the current row
+    Preceding(usize),
+    /// from the current row to the last row
+    Remaining,
+    /// from the first row to the last peer of the current row, peers are the
+    /// rows with the same ORDER BY values
+    RunningPeers,
+    /// from the first peer of the current row to the last row
+    RemainingPeers,
+}
+
+/// 1-based row number in the partition
+fn row_number(reverse: bool) -> Expr {
+    col(WINDOW_ROW).cumcount(reverse) + lit(1u32)
+}
+
+/// whether a row starts a new group of peers, rows with the same `order_by`
+/// values, it does if any of the keys differs from the previous row
+fn new_peer(order_by: &[Expr]) -> Expr {
+    order_by.iter().fold(row_number(false).eq(lit(1u32)), |new, k| {
+        let prev = k.clone().shift(1);
+        let differ = k.clone().is_null().neq(prev.clone().is_null())
+            .or(k.clone().is_not_null().and(prev.clone().is_not_null()).and(k.clone().neq(prev)));
+        new.or(differ.fill_null(lit(false)))
+    })
+}
+
+/// a frame of peers is evaluated like the frame of rows, then each row takes the
+/// value at its last peer (or first peer for `RemainingPeers`)
+fn at_peer(e: Expr, order_by: &[Expr], frame: Frame) -> Expr {
+    let row = col(WINDOW_ROW).cumcount(false);
+    let index = match frame {
+        Frame::RunningPeers => {
+            let last = new_peer(order_by).shift(-1).fill_null(lit(true));
+            when(last).then(row).otherwise(lit(NULL)).backward_fill(None)
+        }
+        Frame::RemainingPeers => when(new_peer(order_by)).then(row).otherwise(lit(NULL)).forward_fill(None),
+        _ => return e,
+    };
+    e.take(index)
+}
+
+/// build the expression of a window function by its (lower cased) name, which
+/// is evaluated over each partition of rows sorted by `order_by`. `count(*)`
+/// has no arguments
+pub(crate) fn window(name: &str, args: Vec<Expr>, order_by: &[Expr], frame: Frame) -> Result<Expr, SqError> {
+    let arity = |n| check_arity(name, &args, n);
+    let rows = match frame {
+        Frame::RunningPeers => Frame::Running,
+        Frame::RemainingPeers => Frame::Remaining,
+        frame => frame,
+    };
+
+    match (name, rows) {
+        ("row_number", _) => {
+            arity(0..=0)?;
+            Ok(row_number(false))
+        }
+        ("rank" | "dense_rank", _) => {
+            arity(0..=0)?;
+            // a row starts a new rank with a new group of peers
+            let new = new_peer(order_by);
+            Ok(if name == "rank" {
+                when(new).then(row_number(false)).otherwise(lit(NULL)).forward_fill(None)
+            } else {
+                new.cast(DataType::UInt32).cumsum(false)
+            })
+        }
+        ("lag" | "lead", _) => {
+            arity(1..=3)?;
+            let n = args.get(1).map(|e| literal_int(name, e)).transpose()?.unwrap_or(1);
+            let n = if name == "lag" { n } else { -n };
+            let e = args[0].clone();
+            Ok(match args.get(2) {
+                Some(default) => e.shift_and_fill(n, default.clone()),
+                None => e.shift(n),
+            })
+        }
+        ("first_value", Frame::Partition | Frame::Running) | ("last_value", Frame::Partition | Frame::Remaining) => {
+            arity(1..=1)?;
+            let e = args[0].clone();
+            Ok(if name == "first_value" { e.first() } else { e.last() })
+        }
+        ("first_value", Frame::Remaining) | ("last_value", Frame::Running | Frame::Preceding(_)) => {
+            arity(1..=1)?;
+            Ok(at_peer(args[0].clone(), order_by, frame))
+        }
+        ("count" | "sum" | "avg" | "mean" | "min" | "max", _) => {
+            arity(if name == "count" { 0..=1 } else { 1..=1 })?;
+            let e = args.first().cloned().unwrap_or_else(|| col(WINDOW_ROW));
+            let counted = e.clone().is_not_null().cast(DataType::UInt32);
+            let e = match rows {
+                Frame::Partition => match name {
+                    "count" => counted.sum(),
+                    "sum" => e.sum(),
+                    "min" => e.min(),
+                    "max" => e.max(),
+                    _ => e.mean(),
+                },
+                Frame::Running | Frame::Remaining => {
+                    let reverse = rows == Frame::Remaining;
+                    // the sum of no values (or only NULLs) is NULL
+                    let count = counted.cumsum(reverse);
+                    let sum = when(count.clone().eq(lit(0u32))).then(lit(NULL))
+                        .otherwise(e.clone().fill_null(lit(0)).cumsum(reverse));
+                    match name {
+                        "count" => count,
+                        "sum" => sum,
+                        "min" => e.cummin(reverse),
+                        "max" => e.cummax(reverse),
+                        _ => sum.cast(DataType::Float64) / count.cast(DataType::Float64),
+                    }
+                }
+                Frame::Preceding(n) => {
+                    let options = RollingOptions {
+                        window_size: Duration::new(n as i64 + 1),
+                        min_periods: 1,
+                        ..Default::default()
+                    };
+                    match name {
+                        "count" => counted.rolling_sum(options),
+                        "sum" => e.rolling_sum(options),
+                        "min" => e.rolling_min(options),
+                        "max" => e.rolling_max(options),
+                        _ => e.cast(DataType::Float64).rolling_mean(options),
+                    }
+                }
+                Frame::RunningPeers | Frame::RemainingPeers => unreachable!(),
+            };
+            Ok(at_peer(e, order_by, frame))
+        }
+        _ => Err(SqError::unsupported(name)),
+    }
+}
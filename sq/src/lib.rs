@@ -3,8 +3,14 @@ use std::io::Cursor;
 use std::ops::{Deref, DerefMut};
 use lazy_static::lazy_static;
 
+mod diagnostic;
 pub mod fetch;
+mod functions;
+mod params;
 pub mod parser;
+mod plan;
+mod prepared;
+mod session;
 
 #[derive(Debug, thiserror::Error)]
 pub enum SqError {
@@ -20,6 +26,12 @@ pub enum SqError {
     LoadError(String),
     #[error("ast: {0}")]
     AstError(String),
+    #[error("schema: {0}")]
+    SchemaError(String),
+    #[error("unsupported: {fragment}{}", .span.map(|s| format!(" at {}", s)).unwrap_or_default())]
+    Unsupported { fragment: String, span: Option<Span> },
+    #[error("{0}")]
+    Diagnostic(Box<Diagnostic>),
     #[error("convert: {0}")]
     ConvertError(#[from] std::num::ParseIntError),
     #[error("convert: {0}")]
@@ -30,8 +42,56 @@ pub enum SqError {
     Utf8Error(#[from] std::str::Utf8Error),
 }
 
+impl SqError {
+    pub(crate) fn unsupported<T: ToString>(fragment: T) -> Self {
+        SqError::Unsupported { fragment: fragment.to_string(), span: None }
+    }
+
+    /// an unsupported part of `fragment` is reported as the whole fragment
+    pub(crate) fn within<T: ToString>(self, fragment: T) -> Self {
+        match self {
+            SqError::Unsupported { .. } => SqError::unsupported(fragment),
+            e => e,
+        }
+    }
+
+    /// locate the offending fragment of an unsupported construct or a
+    /// diagnostic in `sql`
+    pub(crate) fn locate(self, sql: &str) -> Self {
+        match self {
+            SqError::Unsupported { fragment, span: None } => {
+                let span = Span::find(sql, &fragment);
+                SqError::Unsupported { fragment, span }
+            }
+            SqError::Diagnostic(mut d) if d.span.is_none() => {
+                d.span = Span::find(sql, &d.fragment);
+                SqError::Diagnostic(d)
+            }
+            e => e,
+        }
+    }
+
+    /// the error as a diagnostic pointing into the sql, if it has a fragment
+    pub fn diagnostic(&self) -> Option<Diagnostic> {
+        match self {
+            SqError::Diagnostic(d) => Some(*d.clone()),
+            SqError::Unsupported { fragment, span } => Some(Diagnostic {
+                message: format!("unsupported: {}", fragment),
+                fragment: fragment.clone(),
+                span: *span,
+                suggestions: vec![],
+            }),
+            _ => None,
+        }
+    }
+}
+
 use fetch::*;
-use parser::*;
+
+pub use diagnostic::{Diagnostic, Span};
+pub use params::{Param, Params};
+pub use prepared::PreparedQuery;
+pub use session::{Options, Session, TableSource};
 
 #[derive(Debug)]
 pub struct DataSet(DataFrame);
@@ -70,14 +130,14 @@ pub trait Loader {
 }
 
 #[derive(Debug)]
-struct CsvLoader<'a>(&'a Vec<u8>);
+struct CsvLoader<'a>(&'a Vec<u8>, usize);
 
 impl<'a> Loader for CsvLoader<'a> {
     type Error = SqError;
 
     fn load(&self) -> Result<DataSet, Self::Error> {
         let df = CsvReader::new(Cursor::new(self.0))
-            .infer_schema(Some(10))
+            .infer_schema(if self.1 == 0 { None } else { Some(self.1) })
             .finish()?;
         Ok(DataSet(df))
     }
@@ -110,7 +170,11 @@ impl <'a> Loader for CommandLoader<'a> {
         let mut rows = vec![];
 
         let mut s = std::str::from_utf8(self.0.as_slice())?.lines();
-        let schema = s.next().unwrap().split_ascii_whitespace().collect::<Vec<_>>();
+        // a command printing nothing has no header, nor any rows
+        let schema = match s.next() {
+            Some(header) => header.split_ascii_whitespace().collect::<Vec<_>>(),
+            None => return Ok(DataSet(DataFrame::default())),
+        };
 
         for ln in s {
             let r = ln.trim().splitn(schema.len(), &*PAT).map(|s| AnyValue::Utf8(s.trim())).collect();
@@ -124,7 +188,7 @@ impl <'a> Loader for CommandLoader<'a> {
 }
 
 #[derive(Debug)]
-struct GuessLoader<'a>(&'a Vec<u8>);
+struct GuessLoader<'a>(#[allow(dead_code)] &'a Vec<u8>);
 
 impl<'a> Loader for GuessLoader<'a> {
     type Error = SqError;
@@ -134,63 +198,1006 @@ impl<'a> Loader for GuessLoader<'a> {
     }
 }
 
-fn load(data: &FetchData) -> Result<DataSet, SqError> {
-    match data.hint.as_ref().map(|s| s.as_ref()).unwrap_or("") {
-        "csv" => CsvLoader(&data.data).load(),
-        "parquet" => ParquetLoader(&data.data).load(),
-        "console" => CommandLoader(&data.data).load(),
-        _ => GuessLoader(&data.data).load(),
+/// load the fetched data with the loader picked by its hint, along with the name
+/// of the loader
+fn load(data: &FetchData, options: &Options) -> Result<(DataSet, &'static str), SqError> {
+    Ok(match data.hint.as_ref().map(|s| s.as_ref()).unwrap_or("") {
+        "csv" => (CsvLoader(&data.data, options.infer_schema_length).load()?, "CsvLoader"),
+        "parquet" => (ParquetLoader(&data.data).load()?, "ParquetLoader"),
+        "console" => (CommandLoader(&data.data).load()?, "CommandLoader"),
+        _ => (GuessLoader(&data.data).load()?, "GuessLoader"),
+    })
+}
+
+/// the counterpart of `Loader`, encodes a data set to be written to a url
+pub trait Writer {
+    type Error;
+    fn write(&self, ds: &mut DataSet) -> Result<Vec<u8>, Self::Error>;
+}
+
+#[derive(Debug)]
+struct CsvDataWriter {
+    header: bool,
+    delimiter: u8,
+}
+
+impl Writer for CsvDataWriter {
+    type Error = SqError;
+
+    fn write(&self, ds: &mut DataSet) -> Result<Vec<u8>, Self::Error> {
+        let mut buf = vec![];
+        CsvWriter::new(&mut buf)
+            .has_header(self.header)
+            .with_delimiter(self.delimiter)
+            .finish(ds)?;
+        Ok(buf)
     }
 }
 
-pub async fn execute<S: AsRef<str>>(sql: S) -> Result<DataSet, SqError> {
-    let Query {
-        projections,
-        source,
-        condition,
-        limit,
-        offset,
-        order_by
-    } = parse(sql)?;
-
-    match source {
-        Some(source) => {
-            println!("source: [{}]", source);
-            let data = fetch(&source).await?;
-
-            let ds = {
-                let ds = load(&data)?.0.lazy().select(projections);
-                let ds = if condition.is_some() {
-                    ds.filter(condition.unwrap())
-                } else {
-                    ds
-                };
-                let ds = if order_by.len() > 0 {
-                    let (by, asc): (Vec<_>, Vec<_>) = order_by.into_iter().unzip();
-                    ds.sort_by_exprs(by, asc, false)
-                } else {
-                    ds
-                };
-                if offset.is_some() || limit.is_some() {
-                    ds.slice(offset.unwrap_or(0), limit.unwrap_or(usize::MAX) as u32)
-                } else {
-                    ds
-                }
+#[derive(Debug)]
+struct ParquetDataWriter(ParquetCompression);
+
+impl Writer for ParquetDataWriter {
+    type Error = SqError;
+
+    fn write(&self, ds: &mut DataSet) -> Result<Vec<u8>, Self::Error> {
+        let mut buf = vec![];
+        ParquetWriter::new(&mut buf).with_compression(self.0).finish(ds)?;
+        Ok(buf)
+    }
+}
+
+/// writes a JSON array of rows, or a row per line if `lines`
+#[derive(Debug)]
+struct JsonDataWriter {
+    lines: bool,
+}
+
+impl Writer for JsonDataWriter {
+    type Error = SqError;
+
+    fn write(&self, ds: &mut DataSet) -> Result<Vec<u8>, Self::Error> {
+        let mut buf = vec![];
+        let format = if self.lines { JsonFormat::JsonLines } else { JsonFormat::Json };
+        JsonWriter::new(&mut buf).with_json_format(format).finish(ds)?;
+        Ok(buf)
+    }
+}
+
+/// write `ds` to a file url, in the format named by the `FORMAT` option or else
+/// by the extension. rows are appended to an existing CSV or NDJSON file if
+/// `append`, other formats can't be appended to
+fn save(url: &str, ds: &mut DataSet, options: &[(String, String)], append: bool) -> Result<(), SqError> {
+    let path = url.strip_prefix("file://").ok_or_else(|| SqError::unsupported(url))?;
+    let value = |name: &str| options.iter().find(|(o, _)| o == name).map(|(_, v)| v.as_str());
+    // keywords are matched ignoring case, characters like the delimiter are not
+    let option = |name: &str| value(name).map(str::to_lowercase);
+    let format = option("format").unwrap_or_else(|| path.rsplit('.').next().unwrap_or_default().to_lowercase());
+    let exists = std::fs::metadata(path).map(|m| m.len() > 0).unwrap_or(false);
+
+    let known: &[&str] = match format.as_str() {
+        "csv" => &["format", "header", "delimiter"],
+        "parquet" => &["format", "compression"],
+        _ => &["format"],
+    };
+    if let Some((name, _)) = options.iter().find(|(o, _)| !known.contains(&o.as_str())) {
+        return Err(SqError::unsupported(format!("{} option {}", format, name)));
+    }
+
+    let data = match format.as_str() {
+        "csv" => {
+            let header = match option("header").as_deref() {
+                None => !(append && exists),
+                Some("true" | "on" | "1") => true,
+                Some("false" | "off" | "0") => false,
+                Some(v) => return Err(SqError::AstError(format!("invalid HEADER {}", v))),
+            };
+            let delimiter = match value("delimiter").map(str::as_bytes) {
+                None => b',',
+                Some([d]) => *d,
+                Some(_) => return Err(SqError::AstError("DELIMITER should be a single character".to_owned())),
+            };
+            CsvDataWriter { header, delimiter }.write(ds)?
+        }
+        "parquet" if append => return Err(SqError::unsupported(format!("appending to parquet {}", url))),
+        "parquet" => {
+            let compression = match option("compression").as_deref() {
+                None | Some("lz4") => ParquetCompression::Lz4Raw,
+                Some("uncompressed") => ParquetCompression::Uncompressed,
+                Some("snappy") => ParquetCompression::Snappy,
+                Some("gzip") => ParquetCompression::Gzip(None),
+                Some("brotli") => ParquetCompression::Brotli(None),
+                Some("zstd") => ParquetCompression::Zstd(None),
+                Some(c) => return Err(SqError::unsupported(format!("parquet compression {}", c))),
             };
+            ParquetDataWriter(compression).write(ds)?
+        }
+        "ndjson" | "jsonl" => JsonDataWriter { lines: true }.write(ds)?,
+        "json" if append => return Err(SqError::unsupported(format!("appending to json {}", url))),
+        "json" => JsonDataWriter { lines: false }.write(ds)?,
+        format => return Err(SqError::unsupported(format!("format {}", format))),
+    };
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)?;
+    std::io::Write::write_all(&mut file, &data)?;
+    Ok(())
+}
+
+pub async fn execute<S: AsRef<str>>(sql: S) -> Result<DataSet, SqError> {
+    execute_with_params(sql, &Params::default()).await
+}
+
+/// execute `sql` with `params` bound to its placeholders, values are bound as
+/// literals so need no quoting, and a placeholder can also stand for the url of
+/// a source like `select * from ? where location = ?`
+pub async fn execute_with_params<S: AsRef<str>>(sql: S, params: &Params) -> Result<DataSet, SqError> {
+    Session::new().execute_with_params(sql, params).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COVID: &str = "\
+continent,location,total_cases,new_cases
+Africa,Egypt,515000,10
+Africa,Kenya,338000,
+Asia,Japan,22000000,120
+Asia,India,44600000,300
+Asia,China,2000000,5
+Europe,France,37000000,80
+";
+
+    fn csv_url(name: &str, content: &str) -> String {
+        let path = std::env::temp_dir().join(format!("sq-{}-{}.csv", std::process::id(), name));
+        std::fs::write(&path, content).unwrap();
+        format!("file://{}", path.display())
+    }
+
+    #[tokio::test]
+    async fn test_execute_group_by() {
+        let url = csv_url("group_by", COVID);
+        let sql = format!(r#"
+            select continent, count(*), sum(total_cases) as cases, avg(new_cases), max(new_cases)
+            from {}
+            where total_cases > 1000000
+            group by continent
+            order by sum(total_cases) desc
+            "#, url);
+        let ds = execute(sql).await.unwrap();
+
+        assert_eq!(ds.get_column_names(), ["continent", "count(*)", "cases", "avg(new_cases)", "max(new_cases)"]);
+        assert_eq!(ds.height(), 2);
+        assert_eq!(ds.column("continent").unwrap().get(0), AnyValue::Utf8("Asia"));
+        assert_eq!(ds.column("count(*)").unwrap().get(0), AnyValue::UInt32(3));
+        assert_eq!(ds.column("cases").unwrap().get(0), AnyValue::Int64(68600000));
+        assert_eq!(ds.column("max(new_cases)").unwrap().get(1), AnyValue::Int64(80));
+    }
+
+    #[tokio::test]
+    async fn test_execute_whole_table_aggregate() {
+        let url = csv_url("whole_table", COVID);
+        let sql = format!(r#"
+            select count(new_cases), count(distinct continent), min(total_cases), median(new_cases)
+            from {}
+            "#, url);
+        let ds = execute(sql).await.unwrap();
+
+        assert_eq!(ds.shape(), (1, 4));
+        assert_eq!(ds.column("count(new_cases)").unwrap().get(0), AnyValue::UInt32(5));
+        assert_eq!(ds.column("count(DISTINCT continent)").unwrap().get(0), AnyValue::UInt32(3));
+        assert_eq!(ds.column("min(total_cases)").unwrap().get(0), AnyValue::Int64(338000));
+        assert_eq!(ds.column("median(new_cases)").unwrap().get(0), AnyValue::Float64(80.0));
+    }
+
+    #[tokio::test]
+    async fn test_execute_having() {
+        let url = csv_url("having", COVID);
+        let sql = format!(r#"
+            select continent, sum(total_cases) as cases
+            from {}
+            group by continent
+            having count(*) > 1 and cases > 1000000
+            "#, url);
+        let ds = execute(sql).await.unwrap();
 
-            Ok(DataSet(ds.collect()?))
+        assert_eq!(ds.get_column_names(), ["continent", "cases"]);
+        assert_eq!(ds.height(), 1);
+        assert_eq!(ds.column("continent").unwrap().get(0), AnyValue::Utf8("Asia"));
+    }
+
+    #[tokio::test]
+    async fn test_execute_join() {
+        let covid = csv_url("join", COVID);
+        let mut df = df! {
+            "name" => ["Egypt", "Japan", "France", "Peru"],
+            "population" => [104, 125, 68, 33],
+        }.unwrap();
+        let path = std::env::temp_dir().join(format!("sq-{}-join.parquet", std::process::id()));
+        ParquetWriter::new(std::fs::File::create(&path).unwrap()).finish(&mut df).unwrap();
+        let population = format!("file://{}", path.display());
+
+        let sql = format!(r#"
+            select c.location, p.population, new_cases
+            from {} c join {} as p on c.location = p.name and p.population > 100
+            order by c.location
+            "#, covid, population);
+        let ds = execute(sql).await.unwrap();
+        assert_eq!(ds.get_column_names(), ["location", "population", "new_cases"]);
+        assert_eq!(ds.height(), 2);
+        assert_eq!(ds.column("location").unwrap().get(0), AnyValue::Utf8("Egypt"));
+        assert_eq!(ds.column("population").unwrap().get(1), AnyValue::Int32(125));
+
+        let sql = format!("select name from {} p right join {} c on c.location = p.name", population, covid);
+        assert_eq!(execute(sql).await.unwrap().column("name").unwrap().null_count(), 3);
+
+        let sql = format!("select name from {} p full join {} c on c.location = p.name", population, covid);
+        assert_eq!(execute(sql).await.unwrap().height(), 7);
+
+        let sql = format!("select * from {} p anti join {} c on c.location = p.name", population, covid);
+        let ds = execute(sql).await.unwrap();
+        assert_eq!(ds.get_column_names(), ["name", "population"]);
+        assert_eq!(ds.column("name").unwrap().get(0), AnyValue::Utf8("Peru"));
+
+        let sql = format!("select count(*) from {} p, {} c", population, covid);
+        assert_eq!(execute(sql).await.unwrap().column("count(*)").unwrap().get(0), AnyValue::UInt32(24));
+
+        // NULL keys match nothing
+        let l = csv_url("join-null-l", "k,v\n1,x\n,y\n");
+        let r = csv_url("join-null-r", "k,w\n1,p\n,q\n");
+        let heights = [("join", 1), ("left join", 2), ("right join", 2), ("full join", 3), ("semi join", 1), ("anti join", 1)];
+        for (kind, height) in heights {
+            let sql = format!("select * from {} l {} {} r on l.k = r.k", l, kind, r);
+            assert_eq!(execute(sql).await.unwrap().height(), height, "{}", kind);
         }
-        _ => {
-            println!("no source");
-            let df = projections
-                .into_iter()
-                .fold(DataFrame::default().lazy(), |lf, e| {
-                    let nm = e.to_string();
-                    lf.with_column(e.alias(&nm))
-                })
-                .collect()?;
-
-            Ok(DataSet(df))
+        let sql = format!("select v, w from {} l left join {} r on l.k = r.k order by v", l, r);
+        let ds = execute(sql).await.unwrap();
+        assert_eq!(ds.column("w").unwrap().get(1), AnyValue::Null);
+        let sql = format!("select v from {} l anti join {} r using (k)", l, r);
+        assert_eq!(execute(sql).await.unwrap().column("v").unwrap().get(0), AnyValue::Utf8("y"));
+    }
+
+    #[tokio::test]
+    async fn test_execute_subqueries() {
+        let url = csv_url("subqueries", COVID);
+        let cases = |ds: DataSet| ds.column("total_cases").unwrap().i64().unwrap().into_no_null_iter().collect::<Vec<_>>();
+
+        let sql = format!(r#"
+            select t.location, cases from (select location, total_cases as cases from {} where new_cases > 50) t
+            where cases < 30000000
+            "#, url);
+        let ds = execute(sql).await.unwrap();
+        assert_eq!(ds.get_column_names(), ["location", "cases"]);
+        assert_eq!(ds.height(), 1);
+
+        let sql = format!(r#"
+            select total_cases from {0}
+            where continent in (select continent from {0} where location = 'France')
+               or total_cases = (select min(total_cases) from {0})
+            "#, url);
+        assert_eq!(cases(execute(sql).await.unwrap()), [338000, 37000000]);
+
+        let sql = format!(r#"
+            select total_cases from {0} a
+            where not exists (select 1 from {0} b where b.continent = a.continent and b.total_cases > a.total_cases)
+            "#, url);
+        assert_eq!(cases(execute(sql).await.unwrap()), [515000, 44600000, 37000000]);
+
+        let sql = format!(r#"
+            select total_cases from {0}
+            where location not in (select location from {0} where new_cases > 10) and exists (select * from {0})
+            "#, url);
+        assert_eq!(cases(execute(sql).await.unwrap()), [515000, 338000, 2000000]);
+
+        let sql = format!("select location, (select max(new_cases) from {0}) as top from {0} limit 1", url);
+        let ds = execute(sql).await.unwrap();
+        assert_eq!(ds.column("top").unwrap().get(0), AnyValue::Int64(300));
+
+        // NULLs in IN and NOT IN never match
+        let l = csv_url("subqueries-null-l", "k,v\n1,x\n,y\n2,z\n");
+        let r = csv_url("subqueries-null-r", "k,w\n1,a\n,b\n");
+        let values = |ds: DataSet| ds.column("v").unwrap().utf8().unwrap().into_no_null_iter().map(str::to_owned).collect::<Vec<_>>();
+        let sql = format!("select v from {} where k in (select k from {})", l, r);
+        assert_eq!(values(execute(sql).await.unwrap()), ["x"]);
+        let sql = format!("select v from {} where k not in (select k from {})", l, r);
+        assert_eq!(values(execute(sql).await.unwrap()), Vec::<String>::new());
+        let sql = format!("select v from {} where k not in (select k from {} where k is not null)", l, r);
+        assert_eq!(values(execute(sql).await.unwrap()), ["z"]);
+        let sql = format!("select v from {} where k not in (select k from {} where k > 5)", l, r);
+        assert_eq!(values(execute(sql).await.unwrap()), ["x", "y", "z"]);
+        let sql = format!("select v from {} l where k not in (select k from {} r where r.k >= l.k) order by v", l, r);
+        assert_eq!(values(execute(sql).await.unwrap()), ["y", "z"]);
+
+        // so they don't as expressions either
+        let sql = format!("select v, k in (select k from {1}) as i, k not in (select k from {1}) as n from {0}", l, r);
+        let ds = execute(sql).await.unwrap();
+        let column = |name| ds.column(name).unwrap().iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        assert_eq!(column("i"), ["true", "null", "null"]);
+        assert_eq!(column("n"), ["false", "null", "null"]);
+        let sql = format!("select v from {} where k in (select k from {} where k is not null) or v = 'z'", l, r);
+        assert_eq!(values(execute(sql).await.unwrap()), ["x", "z"]);
+        let sql = format!("select v from {} where k not in (select k from {}) or v = 'x'", l, r);
+        assert_eq!(values(execute(sql).await.unwrap()), ["x"]);
+    }
+
+    #[tokio::test]
+    async fn test_execute_ctes() {
+        let url = csv_url("ctes", COVID);
+        let sql = format!(r#"
+            with big as (select continent, location, total_cases from {} where total_cases > 1000000),
+                 totals(name, cases) as (select continent, sum(total_cases) from big group by continent)
+            select b.location, t.cases
+            from big b join totals t on b.continent = t.name
+            where b.location in (select location from big where total_cases > 30000000)
+            order by b.location
+            "#, url);
+        let ds = execute(sql).await.unwrap();
+        assert_eq!(ds.get_column_names(), ["location", "cases"]);
+        assert_eq!(ds.column("location").unwrap().get(0), AnyValue::Utf8("France"));
+        assert_eq!(ds.column("cases").unwrap().get(1), AnyValue::Int64(68600000));
+    }
+
+    #[tokio::test]
+    async fn test_execute_set_operations() {
+        let url = csv_url("set_operations", COVID);
+        let continents = |ds: DataSet| ds.column("continent").unwrap().utf8().unwrap().into_no_null_iter().map(str::to_owned).collect::<Vec<_>>();
+
+        let sql = format!(r#"
+            select continent from {0} where total_cases > 30000000
+            union all select continent from {0} where new_cases > 100
+            order by continent desc
+            "#, url);
+        assert_eq!(continents(execute(sql).await.unwrap()), ["Europe", "Asia", "Asia", "Asia"]);
+
+        let sql = format!("select continent from {0} union select continent from {0} order by continent limit 2", url);
+        assert_eq!(continents(execute(sql).await.unwrap()), ["Africa", "Asia"]);
+
+        let sql = format!(r#"
+            select continent from {0} intersect all select continent from {0} where total_cases > 1000000
+            "#, url);
+        assert_eq!(continents(execute(sql).await.unwrap()), ["Asia", "Asia", "Asia", "Europe"]);
+
+        let sql = format!(r#"
+            select continent from {0} except all select continent from {0} where new_cases > 50
+            order by continent
+            "#, url);
+        assert_eq!(continents(execute(sql).await.unwrap()), ["Africa", "Africa", "Asia"]);
+
+        let sql = format!("select continent from {0} except select continent from {0} where new_cases > 50", url);
+        assert_eq!(continents(execute(sql).await.unwrap()), ["Africa"]);
+
+        let rates = csv_url("set_operations_rates", "location,rate\nEgypt,0.5\nPeru,1.5\n");
+        let sql = format!("select location, new_cases from {} union select * from {}", url, rates);
+        let ds = execute(sql).await.unwrap();
+        assert_eq!(ds.get_column_names(), ["location", "new_cases"]);
+        assert_eq!(ds.column("new_cases").unwrap().dtype(), &DataType::Float64);
+        assert_eq!(ds.height(), 8);
+
+        let sql = format!("select continent, location from {0} union select continent from {0}", url);
+        assert!(matches!(execute(sql).await, Err(SqError::SchemaError(_))));
+        let sql = format!("select continent from {0} union select total_cases from {0}", url);
+        assert!(matches!(execute(sql).await, Err(SqError::SchemaError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_execute_scalar_functions() {
+        let url = csv_url("scalar_functions", COVID);
+        let sql = format!(r#"
+            select upper(location), length(location) as len, substr(location, 2, 3) as sub,
+                   replace(lower(location), 'a', '_') as rep, concat(continent, '/', location, new_cases) as path,
+                   split_part(concat(continent, '-', location), '-', 2) as part,
+                   coalesce(new_cases, 0) as cases, nullif(continent, 'Asia') as other, greatest(new_cases, 100) as top
+            from {}
+            where starts_with(trim(location), 'K') or abs(new_cases - 110) < 15
+            order by length(location) desc, location
+            "#, url);
+        let ds = execute(sql).await.unwrap();
+        assert_eq!(ds.get_column_names(), ["upper(location)", "len", "sub", "rep", "path", "part", "cases", "other", "top"]);
+        assert_eq!(ds.height(), 2);
+        let row = |i| ds.get_row(i).0;
+        assert_eq!(row(0), [
+            AnyValue::Utf8("JAPAN"), AnyValue::UInt32(5), AnyValue::Utf8("apa"), AnyValue::Utf8("j_p_n"),
+            AnyValue::Utf8("Asia/Japan120"), AnyValue::Utf8("Japan"), AnyValue::Int64(120), AnyValue::Null,
+            AnyValue::Int64(120),
+        ]);
+        assert_eq!(ds.column("path").unwrap().get(1), AnyValue::Utf8("Africa/Kenya"));
+        assert_eq!(ds.column("cases").unwrap().get(1), AnyValue::Int64(0));
+
+        let sql = format!(r#"
+            select round(sqrt(total_cases), 1) as r, floor(power(new_cases, 0.5)) as f, ceil(ln(new_cases)) as c,
+                   log10(total_cases) as l, least(new_cases, 100, 50) as m, ifnull(new_cases, 0) as n
+            from {} where location = 'India' or location = 'Kenya'
+            "#, url);
+        let ds = execute(sql).await.unwrap();
+        assert_eq!(ds.column("r").unwrap().get(1), AnyValue::Float64(6678.3));
+        assert_eq!(ds.column("f").unwrap().get(1), AnyValue::Float64(17.0));
+        assert_eq!(ds.column("c").unwrap().get(1), AnyValue::Float64(6.0));
+        assert!((ds.column("l").unwrap().f64().unwrap().get(0).unwrap() - 338000f64.log10()).abs() < 1e-9);
+        assert_eq!(ds.column("m").unwrap().get(1), AnyValue::Int64(50));
+        assert_eq!(ds.column("n").unwrap().get(0), AnyValue::Int64(0));
+
+        let sql = format!("select split_part(location, ',', 0) from {}", url);
+        assert!(matches!(execute(sql).await, Err(SqError::AstError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_execute_cast_and_case() {
+        let url = csv_url("cast_and_case", COVID);
+        let sql = format!(r#"
+            select cast(cast(total_cases as varchar) as bigint) as cases, new_cases::int as new,
+                   try_cast(location as int) as bad, cast('2022-10-01' as date) as day,
+                   try_cast(location as timestamp) as bad_time, '2022-10-01 12:30:00'::timestamp as time,
+                   case when total_cases > 10000000 then 'big' when total_cases > 1000000 then 'medium' else 'small' end as size,
+                   case continent when 'Asia' then 1 end as asia
+            from {}
+            order by case when new_cases > 0 then new_cases else 0 end
+            "#, url);
+        let ds = execute(sql).await.unwrap();
+        assert_eq!(ds.column("cases").unwrap().get(0), AnyValue::Int64(338000));
+        assert_eq!(ds.column("new").unwrap().get(5), AnyValue::Int32(300));
+        assert_eq!(ds.column("bad").unwrap().null_count(), 6);
+        assert_eq!(ds.column("day").unwrap().dtype(), &DataType::Date);
+        assert_eq!(ds.column("bad_time").unwrap().null_count(), 6);
+        assert_eq!(ds.column("time").unwrap().dtype(), &DataType::Datetime(TimeUnit::Microseconds, None));
+        let sizes = ds.column("size").unwrap().utf8().unwrap().into_no_null_iter().collect::<Vec<_>>();
+        assert_eq!(sizes, ["small", "medium", "small", "big", "big", "big"]);
+        assert_eq!(ds.column("asia").unwrap().null_count(), 3);
+
+        let sql = format!("select cast(location as int) from {}", url);
+        assert!(matches!(execute(sql).await, Err(SqError::PolarsError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_execute_predicates() {
+        let url = csv_url("predicates", COVID);
+        let locations = |ds: DataSet| ds.column("location").unwrap().utf8().unwrap().into_no_null_iter().map(str::to_owned).collect::<Vec<_>>();
+        let query = |cond: &str| format!("select location from {} where {} order by location", url, cond);
+
+        let ds = execute(query("continent in ('Africa', 'Europe') and location not in ('Egypt', 'Kenya')")).await.unwrap();
+        assert_eq!(locations(ds), ["France"]);
+        let ds = execute(query("total_cases between 338000 and 2000000")).await.unwrap();
+        assert_eq!(locations(ds), ["China", "Egypt", "Kenya"]);
+        let ds = execute(query("location like '_a%' or location ilike 'CH%'")).await.unwrap();
+        assert_eq!(locations(ds), ["China", "Japan"]);
+        let ds = execute(query("location not like '%a%'")).await.unwrap();
+        assert_eq!(locations(ds), ["Egypt"]);
+        let ds = execute(query("location ~ '^[EF]' and continent not regexp 'rope'")).await.unwrap();
+        assert_eq!(locations(ds), ["Egypt"]);
+        let ds = execute(query("new_cases is null or not (-new_cases < -10)")).await.unwrap();
+        assert_eq!(locations(ds), ["China", "Egypt", "Kenya"]);
+        let ds = execute(query("continent || '/' || location = 'Asia/India'")).await.unwrap();
+        assert_eq!(locations(ds), ["India"]);
+    }
+
+    #[tokio::test]
+    async fn test_execute_distinct() {
+        let url = csv_url("distinct", COVID);
+        let column = |ds: &DataSet, name| ds.column(name).unwrap().utf8().unwrap().into_no_null_iter().map(str::to_owned).collect::<Vec<_>>();
+
+        let sql = format!("select distinct continent from {} order by continent desc", url);
+        let ds = execute(sql).await.unwrap();
+        assert_eq!(column(&ds, "continent"), ["Europe", "Asia", "Africa"]);
+
+        let sql = format!("select distinct continent, lower(continent) as name from {}", url);
+        assert_eq!(execute(sql).await.unwrap().height(), 3);
+
+        let sql = format!(r#"
+            select distinct on (continent) continent, location
+            from {} order by continent, total_cases desc
+            "#, url);
+        let ds = execute(sql).await.unwrap();
+        assert_eq!(ds.get_column_names(), ["continent", "location"]);
+        assert_eq!(column(&ds, "location"), ["Egypt", "India", "France"]);
+
+        let sql = format!("select distinct count(*) as n from {} group by continent order by n", url);
+        let ds = execute(sql).await.unwrap();
+        assert_eq!(ds.column("n").unwrap().u32().unwrap().into_no_null_iter().collect::<Vec<_>>(), [1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_execute_select_items() {
+        let url = csv_url("select-items", COVID);
+        let sql = format!(r#"
+            select total_cases / 1000 as thousands, new_cases + 1, location || '!'
+            from {} where continent = 'Asia'
+            "#, url);
+        let ds = execute(sql).await.unwrap();
+        assert_eq!(ds.get_column_names(), ["thousands", "new_cases + 1", "location || '!'"]);
+        assert_eq!(ds.get_row(0).0, [AnyValue::Int64(22000), AnyValue::Int64(121), AnyValue::Utf8("Japan!")]);
+
+        let sql = format!(r#"
+            select t.* except (continent, total_cases) replace (new_cases * 2 as new_cases)
+            from {} t where location = 'Egypt'
+            "#, url);
+        let ds = execute(sql).await.unwrap();
+        assert_eq!(ds.get_column_names(), ["location", "new_cases"]);
+        assert_eq!(ds.get_row(0).0, [AnyValue::Utf8("Egypt"), AnyValue::Int64(20)]);
+
+        let sql = format!("select u.* from {} t", url);
+        assert!(execute(sql).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_execute_order_by() {
+        let url = csv_url("order-by", COVID);
+        let locations = |ds: &DataSet| {
+            ds.column("location").unwrap().utf8().unwrap().into_no_null_iter().map(str::to_owned).collect::<Vec<_>>()
+        };
+
+        let sql = format!("select location, new_cases * 2 as doubled from {} order by doubled desc nulls first", url);
+        let ds = execute(sql).await.unwrap();
+        assert_eq!(locations(&ds), ["Kenya", "India", "Japan", "France", "Egypt", "China"]);
+
+        let sql = format!("select location, new_cases from {} order by 2 nulls last, 1 desc", url);
+        let ds = execute(sql).await.unwrap();
+        assert_eq!(locations(&ds), ["China", "Egypt", "France", "Japan", "India", "Kenya"]);
+
+        let sql = format!("select continent, sum(new_cases) as cases from {} group by continent order by 2 desc", url);
+        let ds = execute(sql).await.unwrap();
+        let continents = ds.column("continent").unwrap().utf8().unwrap().into_no_null_iter().collect::<Vec<_>>();
+        assert_eq!(continents, ["Asia", "Europe", "Africa"]);
+
+        let sql = format!("select location from {} order by 2", url);
+        assert!(matches!(execute(sql).await, Err(SqError::AstError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_execute_evaluation_order() {
+        let url = csv_url("evaluation-order", COVID);
+        let sql = format!("select location from {} where total_cases > 1000000 order by new_cases desc", url);
+        let ds = execute(sql).await.unwrap();
+        assert_eq!(ds.get_column_names(), ["location"]);
+        let locations = ds.column("location").unwrap().utf8().unwrap().into_no_null_iter().collect::<Vec<_>>();
+        assert_eq!(locations, ["India", "Japan", "France", "China"]);
+
+        let sql = format!("select sum(new_cases) as cases from {} group by continent order by continent desc", url);
+        let ds = execute(sql).await.unwrap();
+        assert_eq!(ds.get_column_names(), ["cases"]);
+        let cases = ds.column("cases").unwrap().i64().unwrap().into_no_null_iter().collect::<Vec<_>>();
+        assert_eq!(cases, [80, 425, 10]);
+
+        let sql = format!(r#"
+            select continent from {} group by continent having count(*) > 1 order by max(total_cases) desc
+            "#, url);
+        let ds = execute(sql).await.unwrap();
+        assert_eq!(ds.get_column_names(), ["continent"]);
+        let continents = ds.column("continent").unwrap().utf8().unwrap().into_no_null_iter().collect::<Vec<_>>();
+        assert_eq!(continents, ["Asia", "Africa"]);
+    }
+
+    #[tokio::test]
+    async fn test_execute_literals() {
+        let url = csv_url("literals", COVID);
+        let sql = format!(r#"
+            select date '2022-01-02' as d, timestamp '2022-01-02 03:04:05' as t, interval '2' day as i,
+                   null as n, 7 / 2 as q, total_cases > 1000000 = true as big
+            from {} where new_cases > 100 limit 1
+            "#, url);
+        let ds = execute(sql).await.unwrap();
+        assert_eq!(ds.get_row(0).0, [
+            AnyValue::Date(18994),
+            AnyValue::Datetime(1641092645000000, TimeUnit::Microseconds, &None),
+            AnyValue::Duration(2 * 86400 * 1000000, TimeUnit::Microseconds),
+            AnyValue::Null,
+            AnyValue::Int64(3),
+            AnyValue::Boolean(true),
+        ]);
+
+        // without FROM, constants are selected from a single row
+        let height = |sql: &'static str| async move { execute(sql).await.unwrap().height() };
+        assert_eq!(height("select 1 as a, 'x' as b").await, 1);
+        assert_eq!(height("select 1 as a where false").await, 0);
+        assert_eq!(height("select 1 as a where 1 = 1 order by a limit 1").await, 1);
+        assert_eq!(height("select 1 as a limit 0").await, 0);
+        assert_eq!(height("select 1 as a offset 1").await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_execute_temporal() {
+        let url = csv_url("temporal", "d,t\n2022-01-15,2022-01-15 20:30\n2022-03-31,2022-03-31 02:00\n2021-12-31,\n");
+        let sql = format!(r#"
+            select to_date(d) as day, extract(year from to_date(d)) as y, date_part('dow', to_date(d)) as dow,
+                   date_trunc('month', to_date(d)) as m, date_trunc('week', to_date(d)) as w,
+                   to_date(d) + interval '2 days' as later,
+                   to_timestamp(t, '%Y-%m-%d %H:%M') at time zone '+08:00' as utc,
+                   to_timestamp(t, '%Y-%m-%d %H:%M') at time zone 'Europe/Paris' as paris,
+                   convert_timezone('Europe/Paris', to_timestamp(t, '%Y-%m-%d %H:%M')) as local,
+                   extract(epoch from to_timestamp(t, '%Y-%m-%d %H:%M')) as e
+            from {} where to_date(d) >= date '2022-01-01' order by day
+            "#, url);
+        let ds = execute(sql).await.unwrap();
+        assert_eq!(ds.height(), 2);
+        assert_eq!(ds.get_row(0).0, [
+            AnyValue::Date(19007),
+            AnyValue::Int32(2022),
+            AnyValue::UInt32(6),
+            AnyValue::Date(18993),
+            AnyValue::Date(19002),
+            AnyValue::Date(19009),
+            AnyValue::Datetime((1642278600 - 8 * 3600) * 1000000, TimeUnit::Microseconds, &None),
+            AnyValue::Datetime((1642278600 - 3600) * 1000000, TimeUnit::Microseconds, &None),
+            AnyValue::Datetime((1642278600 + 3600) * 1000000, TimeUnit::Microseconds, &None),
+            AnyValue::Float64(1642278600.0),
+        ]);
+        // summer time in Paris from the end of march
+        assert_eq!(ds.column("paris").unwrap().get(1), AnyValue::Datetime((1648692000 - 7200) * 1000000, TimeUnit::Microseconds, &None));
+        assert_eq!(ds.column("dow").unwrap().get(1), AnyValue::UInt32(4));
+        assert_eq!(ds.column("w").unwrap().get(1), AnyValue::Date(19079));
+
+        let sql = format!("select count(*) as n from {} where to_date(d) > current_date", url);
+        let ds = execute(sql).await.unwrap();
+        assert_eq!(ds.column("n").unwrap().get(0), AnyValue::UInt32(0));
+
+        // local times skipped or repeated by daylight saving changes are standard time
+        let url = csv_url("dst", "t\n2022-03-27 02:30\n2022-10-30 02:30\n");
+        let sql = format!("select to_timestamp(t, '%Y-%m-%d %H:%M') at time zone 'Europe/Paris' as utc from {}", url);
+        let ds = execute(sql).await.unwrap();
+        assert_eq!(ds.column("utc").unwrap().get(0), AnyValue::Datetime(1648344600 * 1000000, TimeUnit::Microseconds, &None));
+        assert_eq!(ds.column("utc").unwrap().get(1), AnyValue::Datetime(1667093400 * 1000000, TimeUnit::Microseconds, &None));
+    }
+
+    #[tokio::test]
+    async fn test_execute_unsupported_url() {
+        let res = execute("select * from ftp://example.com/a.csv").await;
+        assert!(matches!(res, Err(SqError::Unsupported { .. })));
+        for url in ["file:/tmp/a.csv", "cmd:ls", "cmd://", "cmd://?aux"] {
+            let res = execute(format!("select * from {}", url)).await;
+            assert!(matches!(res, Err(SqError::Unsupported { .. })), "{}", url);
         }
     }
+
+    #[tokio::test]
+    async fn test_execute_empty_command() {
+        let ds = execute("select * from cmd://true").await.unwrap();
+        assert_eq!(ds.height(), 0);
+        assert_eq!(ds.width(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_execute_diagnostic() {
+        let url = csv_url("diagnostic", COVID);
+        let sql = format!("select location, totl_cases\nfrom {}\nwhere new_cases > 10", url);
+        let e = execute(&sql).await.unwrap_err();
+        let d = e.diagnostic().unwrap();
+        assert_eq!(d.fragment, "totl_cases");
+        assert_eq!(d.span, Some(Span { line: 1, column: 18 }));
+        assert_eq!(d.suggestions, ["total_cases"]);
+        assert_eq!(e.to_string(), "column totl_cases not found at line 1, column 18, did you mean total_cases?");
+
+        // output columns are suggested too
+        let sql = format!("select continent, sum(total_cases) as cases from {} group by continent order by cass", url);
+        let d = execute(&sql).await.unwrap_err().diagnostic().unwrap();
+        assert_eq!(d.span.map(|s| s.column), Some(sql.len() - 3));
+        assert_eq!(d.suggestions, ["cases"]);
+
+        let sql = format!("select * from {} a join {} b on a.location = b.locaton", url, url);
+        let d = execute(&sql).await.unwrap_err().diagnostic().unwrap();
+        assert_eq!(d.fragment, "b.locaton");
+        assert_eq!(d.suggestions, ["location"]);
+    }
+
+    #[tokio::test]
+    async fn test_execute_params() {
+        let url = csv_url("params", COVID);
+        let sql = "select location, total_cases from ? where continent = :continent and new_cases >= $2 order by 2";
+        let params = Params::new().bind(url.as_str()).bind(50).bind_named("continent", "Asia");
+        let ds = execute_with_params(sql, &params).await.unwrap();
+        assert_eq!(ds.column("location").unwrap().utf8().unwrap().into_no_null_iter().collect::<Vec<_>>(), ["Japan", "India"]);
+
+        // quotes in values need no escaping
+        let sql = "select location from ? where location = ?";
+        let params = Params::new().bind(url.as_str()).bind("x' or '1' = '1");
+        assert_eq!(execute_with_params(sql, &params).await.unwrap().height(), 0);
+
+        let date = polars::export::chrono::NaiveDate::from_ymd_opt(2022, 1, 1).unwrap();
+        let params = Params::new().bind(date).bind(None::<i64>).bind(url.as_str());
+        let ds = execute_with_params("select ? as d, ? as n from ? limit 1", &params).await.unwrap();
+        assert_eq!(ds.column("d").unwrap().dtype(), &DataType::Date);
+        assert_eq!(ds.column("n").unwrap().get(0), AnyValue::Null);
+
+        assert!(execute("select * from ?").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_execute_prepared() {
+        let path = std::env::temp_dir().join(format!("sq-{}-prepared.csv", std::process::id()));
+        let url = csv_url("prepared", COVID);
+        assert!(PreparedQuery::new("select 1; drop table x").is_err());
+        let mut q = PreparedQuery::new("select location, new_cases * 2 as doubled from ? where continent = ?").unwrap();
+        assert_eq!(q.sources(), ["?1"]);
+        assert_eq!(q.params(), ["?1", "?2"]);
+        assert!(q.schema().is_none());
+
+        let params = Params::new().bind(url.as_str()).bind("Africa");
+        let schema = q.validate(&params).await.unwrap();
+        assert_eq!(schema.iter_names().collect::<Vec<_>>(), ["location", "doubled"]);
+        assert_eq!(q.schema().unwrap().get("doubled"), Some(&DataType::Int64));
+        assert_eq!(q.execute(&params).await.unwrap().height(), 2);
+        assert_eq!(q.execute(&Params::new().bind(url.as_str()).bind("Asia")).await.unwrap().height(), 3);
+
+        // sources are fetched again on every execution
+        std::fs::write(&path, format!("{}Africa,Nigeria,266000,1\n", COVID)).unwrap();
+        assert_eq!(q.execute(&params).await.unwrap().height(), 3);
+
+        // so is now()
+        let mut q = PreparedQuery::new("select now() as t from ? limit 1").unwrap();
+        let params = Params::new().bind(url.as_str());
+        let first = q.execute(&params).await.unwrap().column("t").unwrap().get(0).to_string();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let second = q.execute(&params).await.unwrap().column("t").unwrap().get(0).to_string();
+        assert!(second > first, "{} is not after {}", second, first);
+    }
+
+    #[tokio::test]
+    async fn test_execute_script() {
+        let url = csv_url("script", COVID);
+        let sql = format!("
+            create view big (country, cases) as select location, total_cases from {} where total_cases > 1000000;
+            select count(*) as n from big;
+            create table top as select * from big order by cases desc limit 2;
+            create view top_asia as select t.country from top t join {} c on t.country = c.location where continent = 'Asia';
+            select * from top_asia;
+            set infer_schema_length = 0;
+            ", url, url);
+        let mut session = Session::new();
+        let res = session.execute_script(&sql).await.unwrap();
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].column("n").unwrap().get(0), AnyValue::UInt32(4));
+        assert_eq!(res[1].column("country").unwrap().get(0), AnyValue::Utf8("India"));
+        assert_eq!(session.options().infer_schema_length, 0);
+
+        let err = session.execute_script("create table top as select 1").await.unwrap_err();
+        assert_eq!(err.to_string(), "ast: relation top already exists");
+        assert!(session.execute_script("set no_such_option = 1").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_execute_session() {
+        let path = std::env::temp_dir().join(format!("sq-{}-session.csv", std::process::id()));
+        let url = csv_url("session", COVID);
+        let mut session = Session::new();
+        session.register("covid", url.as_str());
+        let df = df!("continent" => ["Asia", "Europe"], "code" => ["AS", "EU"]).unwrap();
+        session.register("codes", df);
+        assert_eq!(session.tables(), ["codes", "covid"]);
+
+        let sql = "select code, count(*) as n from covid join codes using (continent) group by code order by code";
+        let ds = session.execute(sql).await.unwrap();
+        assert_eq!(ds.column("n").unwrap().u32().unwrap().into_no_null_iter().collect::<Vec<_>>(), [3, 1]);
+
+        // a registered url is fetched once
+        std::fs::remove_file(&path).unwrap();
+        let ds = session.execute_with_params("select * from covid where location = ?", &Params::new().bind("Japan")).await.unwrap();
+        assert_eq!(ds.height(), 1);
+
+        assert!(session.deregister("covid"));
+        assert!(!session.deregister("covid"));
+        assert_eq!(session.tables(), ["codes"]);
+        let err = session.execute("select * from covid").await.unwrap_err();
+        assert_eq!(err.to_string(), "relation covid does not exist at line 1, column 15");
+        let err = session.execute("select * from code").await.unwrap_err();
+        assert_eq!(err.diagnostic().unwrap().suggestions, ["codes"]);
+        let err = execute("select * from files").await.unwrap_err();
+        assert_eq!(err.to_string(), "relation files does not exist at line 1, column 15");
+    }
+
+    #[tokio::test]
+    async fn test_execute_views() {
+        let path = std::env::temp_dir().join(format!("sq-{}-views.csv", std::process::id()));
+        let url = csv_url("views", COVID);
+        let mut session = Session::new();
+        session.register("raw", url.as_str());
+        let sql = format!("
+            create temp view africa as select location, new_cases from {} where continent = 'Africa';
+            create view reported as select * from africa where new_cases is not null;
+            create table snapshot as select * from africa;
+            ", url);
+        session.execute(&sql).await.unwrap();
+        assert_eq!(session.tables(), ["africa", "raw", "reported", "snapshot"]);
+        assert_eq!(session.execute("select * from reported").await.unwrap().height(), 1);
+
+        // views are evaluated again against the source, tables are not
+        std::fs::write(&path, format!("{}Africa,Nigeria,266000,1\n", COVID)).unwrap();
+        assert_eq!(session.execute("select * from reported").await.unwrap().height(), 2);
+        assert_eq!(session.execute("select * from snapshot").await.unwrap().height(), 2);
+
+        let err = session.execute("drop view africa").await.unwrap_err();
+        assert_eq!(err.to_string(), "ast: cannot drop africa because view reported depends on it");
+        let err = session.execute("drop table reported").await.unwrap_err();
+        assert_eq!(err.to_string(), "ast: table reported does not exist");
+        session.execute("drop view reported, africa; drop table if exists snapshot, nothing").await.unwrap();
+        assert_eq!(session.tables(), ["raw"]);
+        let err = session.execute("select * from africa").await.unwrap_err();
+        assert_eq!(err.to_string(), "relation africa does not exist at line 1, column 15");
+
+        session.execute("create view v as select 1 as a, 'x' as b, 2.5").await.unwrap();
+        let ds = session.execute("select a, b from v").await.unwrap();
+        assert_eq!(ds.get_row(0).0, [AnyValue::Int64(1), AnyValue::Utf8("x")]);
+        assert_eq!(session.execute("select * from v").await.unwrap().get_column_names(), ["a", "b", "2.5"]);
+        assert!(session.execute("create view v as select 2 as a").await.is_err());
+        // a failed replace keeps the old view
+        assert!(session.execute("create or replace view v as select c").await.is_err());
+        assert!(session.execute("create or replace view v as select * from v").await.is_err());
+        assert_eq!(session.execute("select a from v").await.unwrap().get_row(0).0, [AnyValue::Int64(1)]);
+        // raw is first fetched now, after the source changed
+        session.execute("create or replace view v as select location from raw").await.unwrap();
+        assert_eq!(session.execute("select * from v").await.unwrap().height(), 7);
+        session.execute("create table if not exists raw as select 1").await.unwrap();
+        let err = session.execute("select * from no_such_view").await.unwrap_err();
+        assert_eq!(err.diagnostic().unwrap().message, "relation no_such_view does not exist");
+
+        // any relation counts as existing, including views and urls not fetched yet
+        session.register("later", url.as_str());
+        session.execute("create table if not exists later as select 1; create table if not exists v as select 1").await.unwrap();
+        assert_eq!(session.execute("select * from later").await.unwrap().height(), 7);
+        let sql = format!("create or replace table later as select * from {} limit 1; create or replace table v as select 1 as a", url);
+        session.execute(sql).await.unwrap();
+        assert_eq!(session.tables(), ["later", "raw", "v"]);
+        assert_eq!(session.execute("select * from later").await.unwrap().height(), 1);
+        assert_eq!(session.execute("select a from v").await.unwrap().height(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_execute_copy() {
+        let url = csv_url("copy", COVID);
+        let out = |ext: &str| std::env::temp_dir().join(format!("sq-{}-copy-out.{}", std::process::id(), ext));
+        let mut session = Session::new();
+        session.register("covid", url.as_str());
+
+        let sql = format!(
+            "copy (select location, total_cases from covid where continent = 'Asia') to 'file://{}' (format parquet, compression zstd)",
+            out("parquet").display());
+        session.execute(&sql).await.unwrap();
+        let ds = session.execute(format!("select * from file://{}", out("parquet").display())).await.unwrap();
+        assert_eq!(ds.shape(), (3, 2));
+
+        // rows are appended to CSV without repeating the header
+        let csv = out("csv");
+        let sql = format!("
+            copy covid to 'file://{}' (delimiter ';');
+            insert into file://{} select * from covid where continent = 'Europe';
+            ", csv.display(), csv.display());
+        session.execute(&sql).await.unwrap();
+        let content = std::fs::read_to_string(&csv).unwrap();
+        assert_eq!(content.lines().count(), 8);
+        assert!(content.starts_with("continent;location;total_cases;new_cases\n"));
+        assert!(content.ends_with("Europe,France,37000000,80\n"));
+
+        let sql = format!("insert into file://{} select * from covid", out("parquet").display());
+        assert!(matches!(session.execute(&sql).await, Err(SqError::Unsupported { .. })));
+
+        // .json is an array of rows, ndjson and jsonl a row per line
+        let sql = format!("
+            copy (select location, new_cases from covid limit 2) to 'file://{}';
+            copy (select location, new_cases from covid limit 2) to 'file://{}';
+            ", out("json").display(), out("jsonl").display());
+        session.execute(&sql).await.unwrap();
+        let rows = r#"{"location":"Egypt","new_cases":10}"#;
+        let json = std::fs::read_to_string(out("json")).unwrap();
+        assert!(json.starts_with(&format!("[{},", rows)) && json.ends_with(']'), "{}", json);
+        let jsonl = std::fs::read_to_string(out("jsonl")).unwrap();
+        assert_eq!(jsonl.lines().count(), 2);
+        assert!(jsonl.starts_with(&format!("{}\n", rows)), "{}", jsonl);
+        let sql = format!("insert into file://{} select location, new_cases from covid", out("json").display());
+        assert!(matches!(session.execute(&sql).await, Err(SqError::Unsupported { .. })));
+        let sql = format!("copy covid to 'file://{}' (compression zstd)", csv.display());
+        assert!(matches!(session.execute(&sql).await, Err(SqError::Unsupported { .. })));
+
+        session.execute("create table t as select location from covid limit 1").await.unwrap();
+        session.execute("insert into t select location from covid where continent = 'Asia'").await.unwrap();
+        assert_eq!(session.execute("select * from t").await.unwrap().height(), 4);
+        assert!(session.execute("insert into t select 1 as location").await.is_err());
+
+        // keywords ignore case, the delimiter doesn't
+        let sql = format!(
+            "copy (select location, new_cases from covid limit 1) to 'file://{}' (FORMAT CSV, DELIMITER 'T')",
+            out("txt").display());
+        session.execute(&sql).await.unwrap();
+        assert_eq!(std::fs::read_to_string(out("txt")).unwrap(), "locationTnew_cases\nEgyptT10\n");
+
+        // rows inserted into a registered url are appended to it
+        let log = csv_url("copy-log", "location,new_cases\nEgypt,10\n");
+        session.register("log", log.as_str());
+        assert_eq!(session.execute("select * from log").await.unwrap().height(), 1);
+        session.execute("insert into log select location, new_cases from covid where continent = 'Asia'").await.unwrap();
+        assert_eq!(session.execute("select * from log").await.unwrap().height(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_execute_explain() {
+        let url = csv_url("explain", COVID);
+        let rows = |ds: &DataSet| {
+            let names = ds.column("name").unwrap().utf8().unwrap().into_no_null_iter().map(str::to_owned);
+            let values = ds.column("value").unwrap().utf8().unwrap().into_no_null_iter().map(str::to_owned);
+            names.zip(values).collect::<Vec<_>>()
+        };
+
+        let sql = format!("explain select location from {} where total_cases > 1000000", url);
+        let ds = execute(&sql).await.unwrap();
+        let explained = rows(&ds);
+        assert_eq!(explained[0], ("resolved".to_owned(), format!("{} fetched by FileFetcher, loaded by CsvLoader", url)));
+        let names = explained.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["resolved", "projections", "source", "condition", "plan"]);
+        assert!(explained[4].1.contains("FILTER"));
+
+        let mut session = Session::new();
+        session.register("covid", url.as_str());
+        let ds = session.execute("explain analyze select continent, count(*) from covid group by continent").await.unwrap();
+        let explained = rows(&ds);
+        let names = explained.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names[0], "resolved");
+        assert!(names.ends_with(&[&*format!("fetch {}", url), &*format!("load {}", url), "planning", "execution", "rows"]));
+        assert_eq!(explained.last().unwrap().1, "3");
+
+        // cached in the session after the first query
+        let ds = session.execute("explain select * from covid").await.unwrap();
+        assert_eq!(rows(&ds)[0].1, "covid, a table of the session");
+    }
+
+    #[tokio::test]
+    async fn test_execute_windows() {
+        let url = csv_url("windows", COVID);
+        let sql = format!(r#"
+            select location,
+                   row_number() over (partition by continent order by total_cases desc) as n,
+                   rank() over (order by continent) as r,
+                   dense_rank() over (order by continent) as d,
+                   lag(location) over (partition by continent order by location) as prev,
+                   lead(new_cases, 1, 0) over (order by location) as next,
+                   first_value(location) over (partition by continent order by total_cases) as smallest,
+                   last_value(location) over (partition by continent order by total_cases
+                                              rows between unbounded preceding and unbounded following) as largest,
+                   sum(new_cases) over (partition by continent) as total,
+                   sum(total_cases) over (order by location) as running,
+                   count(*) over (order by location rows between 1 preceding and current row) as pair
+            from {}
+            "#, url);
+        let ds = execute(sql).await.unwrap();
+        // rows keep the order of the source
+        let row = |i| ds.get_row(i).0;
+        // Japan
+        assert_eq!(row(2), [
+            AnyValue::Utf8("Japan"), AnyValue::UInt32(2), AnyValue::UInt32(3), AnyValue::UInt32(2),
+            AnyValue::Utf8("India"), AnyValue::Null, AnyValue::Utf8("China"), AnyValue::Utf8("India"),
+            AnyValue::Int64(425), AnyValue::Int64(106115000), AnyValue::UInt32(2),
+        ]);
+        // China
+        assert_eq!(row(4), [
+            AnyValue::Utf8("China"), AnyValue::UInt32(3), AnyValue::UInt32(3), AnyValue::UInt32(2),
+            AnyValue::Null, AnyValue::Int64(10), AnyValue::Utf8("China"), AnyValue::Utf8("India"),
+            AnyValue::Int64(425), AnyValue::Int64(2000000), AnyValue::UInt32(1),
+        ]);
+
+        // with ORDER BY, the default frame includes the peers of a row
+        let sql = format!(r#"
+            select sum(new_cases) over (order by continent) as peers,
+                   sum(new_cases) over (order by continent, location rows unbounded preceding) as rows,
+                   count(*) over (order by continent range between current row and unbounded following) as remaining,
+                   sum(new_cases) over (order by total_cases rows unbounded preceding) as smallest,
+                   max(new_cases) over (partition by continent order by continent) as partition
+            from {}
+            "#, url);
+        let ds = execute(sql).await.unwrap();
+        let column = |name| ds.column(name).unwrap().iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        assert_eq!(column("peers"), ["10", "10", "435", "435", "435", "515"]);
+        assert_eq!(column("rows"), ["10", "10", "435", "315", "15", "515"]);
+        assert_eq!(column("remaining"), ["6", "6", "4", "4", "4", "1"]);
+        assert_eq!(column("smallest"), ["10", "null", "135", "515", "15", "215"]);
+        assert_eq!(column("partition"), ["10", "10", "300", "300", "300", "80"]);
+
+        // top-N per group
+        let sql = format!(r#"
+            select location from (
+                select location, row_number() over (partition by continent order by total_cases desc) as n from {}
+            ) t where n = 1 order by location
+            "#, url);
+        let ds = execute(sql).await.unwrap();
+        let locations = ds.column("location").unwrap().utf8().unwrap().into_no_null_iter().collect::<Vec<_>>();
+        assert_eq!(locations, ["Egypt", "France", "India"]);
+    }
 }
@@ -1,22 +1,30 @@
 use std::ops::Deref;
 
-use sq::execute;
+use sq::Session;
 
 #[tokio::main]
 async fn main() -> Result<(), sq::SqError> {
-    let sql = if let Some(sql) = std::env::args().nth(1) {
-        sql
-    } else {
-        r#" select 'welcome', 'to', 'sq' "#.to_owned()
+    let mut args = std::env::args().skip(1);
+    let sql = match (args.next(), args.next()) {
+        // a script of `;` separated statements
+        (Some(flag), Some(path)) if flag == "-f" => std::fs::read_to_string(path)?,
+        (Some(sql), _) => sql,
+        _ => r#" select 'welcome', 'to', 'sq' "#.to_owned(),
     };
 
     std::env::set_var("POLARS_FMT_MAX_ROWS", "-1"); // -1 force to height
     std::env::set_var("POLARS_FMT_TABLE_HIDE_COLUMN_DATA_TYPES", "1");
-    match execute(&sql).await {
-        Ok(ds) => {
-            println!("{}", ds.deref());
+    std::env::set_var("POLARS_FMT_STR_LEN", "1000"); // EXPLAIN has long values
+    match Session::new().execute_script(&sql).await {
+        Ok(res) => {
+            for ds in res {
+                println!("{}", ds.deref());
+            }
         }
-        Err(e) => println!("{}", e),
+        Err(e) => match e.diagnostic() {
+            Some(d) => print!("{}", d.render(&sql)),
+            None => println!("{}", e),
+        },
     }
 
     Ok(())
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+
+use polars::export::chrono::{NaiveDate, NaiveDateTime};
+use polars::prelude::*;
+
+use super::parser::PARAM_PLACEHOLDER;
+use super::SqError;
+
+/// a value bound to a placeholder of a query
+#[derive(Debug, Clone, PartialEq)]
+pub enum Param {
+    Null,
+    Boolean(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Date(NaiveDate),
+    Timestamp(NaiveDateTime),
+}
+
+impl Param {
+    fn to_expr(&self) -> Expr {
+        match self {
+            Param::Null => Expr::Literal(LiteralValue::Null),
+            Param::Boolean(b) => Expr::Literal(LiteralValue::Boolean(*b)),
+            Param::Int(n) => Expr::Literal(LiteralValue::Int64(*n)),
+            Param::Float(f) => Expr::Literal(LiteralValue::Float64(*f)),
+            Param::Str(s) => Expr::Literal(LiteralValue::Utf8(s.clone())),
+            Param::Date(d) => {
+                let dt = d.and_hms_opt(0, 0, 0).unwrap_or_default();
+                Expr::Literal(LiteralValue::DateTime(dt, TimeUnit::Milliseconds)).cast(DataType::Date)
+            }
+            Param::Timestamp(dt) => Expr::Literal(LiteralValue::DateTime(*dt, TimeUnit::Microseconds)),
+        }
+    }
+}
+
+impl From<bool> for Param {
+    fn from(v: bool) -> Self {
+        Param::Boolean(v)
+    }
+}
+
+impl From<i32> for Param {
+    fn from(v: i32) -> Self {
+        Param::Int(v as i64)
+    }
+}
+
+impl From<i64> for Param {
+    fn from(v: i64) -> Self {
+        Param::Int(v)
+    }
+}
+
+impl From<f64> for Param {
+    fn from(v: f64) -> Self {
+        Param::Float(v)
+    }
+}
+
+impl From<&str> for Param {
+    fn from(v: &str) -> Self {
+        Param::Str(v.to_owned())
+    }
+}
+
+impl From<String> for Param {
+    fn from(v: String) -> Self {
+        Param::Str(v)
+    }
+}
+
+impl From<NaiveDate> for Param {
+    fn from(v: NaiveDate) -> Self {
+        Param::Date(v)
+    }
+}
+
+impl From<NaiveDateTime> for Param {
+    fn from(v: NaiveDateTime) -> Self {
+        Param::Timestamp(v)
+    }
+}
+
+impl<T: Into<Param>> From<Option<T>> for Param {
+    fn from(v: Option<T>) -> Self {
+        v.map_or(Param::Null, Into::into)
+    }
+}
+
+/// values of the placeholders of a query, `?` and `$1` are bound by position,
+/// `:name` and `$name` by name
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Params {
+    positional: Vec<Param>,
+    named: HashMap<String, Param>,
+}
+
+impl Params {
+    pub fn new() -> Self {
+        Params::default()
+    }
+
+    /// bind the next positional placeholder
+    pub fn bind<P: Into<Param>>(mut self, value: P) -> Self {
+        self.positional.push(value.into());
+        self
+    }
+
+    /// bind the placeholder named `name`, without its `:` or `$`
+    pub fn bind_named<P: Into<Param>>(mut self, name: &str, value: P) -> Self {
+        self.named.insert(name.to_owned(), value.into());
+        self
+    }
+
+    fn get(&self, placeholder: &str) -> Result<&Param, SqError> {
+        let name = placeholder.get(1..).unwrap_or_default();
+        let found = match name.parse::<usize>() {
+            Ok(i) if placeholder.starts_with(['?', '$']) => i.checked_sub(1).and_then(|i| self.positional.get(i)),
+            _ => self.named.get(name),
+        };
+        found.ok_or_else(|| SqError::AstError(format!("no value bound to parameter {}", placeholder)))
+    }
+
+    /// the url bound to a table name that is a placeholder, `None` if it's not
+    pub(crate) fn source(&self, name: &str) -> Result<Option<String>, SqError> {
+        if !name.starts_with(['?', '$', ':']) {
+            return Ok(None);
+        }
+        match self.get(name)? {
+            Param::Str(url) => Ok(Some(url.clone())),
+            p => Err(SqError::AstError(format!("parameter {} is used as a source but bound to {:?}", name, p))),
+        }
+    }
+
+    /// substitute the bound values for the placeholder columns in `e`
+    pub(crate) fn bind_expr(&self, e: &mut Expr) -> Result<(), SqError> {
+        let mut res = Ok(());
+        e.mutate().apply(|e| {
+            if let Expr::Column(name) = e {
+                if let Some(placeholder) = name.strip_prefix(PARAM_PLACEHOLDER) {
+                    match self.get(placeholder) {
+                        Ok(p) => *e = p.to_expr(),
+                        Err(err) => {
+                            res = Err(err);
+                            return false;
+                        }
+                    }
+                }
+            }
+            true
+        });
+        res
+    }
+}
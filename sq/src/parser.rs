@@ -1,10 +1,12 @@
+use std::cell::RefCell;
 use std::sync::Arc;
 
-use sqlparser::{ast, dialect, parser};
+use sqlparser::{ast, dialect, parser, tokenizer};
 use polars::lazy::dsl;
-use polars::prelude::LiteralValue;
+use polars::export::chrono::NaiveDateTime;
+use polars::prelude::{DataType, LiteralValue, TimeUnit};
 
-use super::SqError;
+use super::{functions, Params, SqError};
 
 #[derive(Debug)]
 struct MyDialect {
@@ -42,29 +44,226 @@ impl Default for MyDialect {
 impl MyDialect {
     pub fn new() -> MyDialect {
         MyDialect {
-            g: dialect::GenericDialect::default(),
+            g: dialect::GenericDialect,
         }
     }
 }
 
+// a relation in the FROM clause
+#[derive(Debug, Clone, PartialEq)]
+pub enum Relation {
+    /// a table referenced by url, optionally aliased
+    Table { name: String, alias: Option<String> },
+    /// a subquery in FROM, columns can be renamed by the alias like `t(a, b)`
+    Derived { query: Box<Query>, alias: Option<String>, columns: Vec<String> },
+    /// `left UNION|INTERSECT|EXCEPT [ALL] right`, columns are named by the left
+    SetOperation { op: SetOperator, all: bool, left: Box<Query>, right: Box<Query> },
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SetOperator {
+    Union,
+    Intersect,
+    Except,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum JoinKind {
+    Inner,
+    Left,
+    Right,
+    Full,
+    Cross,
+    Semi,
+    Anti,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JoinConstraint {
+    On(dsl::Expr),
+    Using(Vec<String>),
+    None,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Join {
+    pub(crate) relation: Relation,
+    pub(crate) kind: JoinKind,
+    pub(crate) constraint: JoinConstraint,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubqueryKind {
+    /// `(select ...)` evaluated to a single value
+    Scalar,
+    /// `expr [NOT] IN (select ...)`
+    In { expr: dsl::Expr, negated: bool },
+    /// `[NOT] EXISTS (select ...)`
+    Exists { negated: bool },
+}
+
+/// a subquery in an expression, which is referred to by a placeholder column
+/// named by `subquery_placeholder`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Subquery {
+    pub(crate) query: Query,
+    pub(crate) kind: SubqueryKind,
+}
+
+pub(crate) const SUBQUERY_PLACEHOLDER: &str = "__sq_subquery_";
+
+pub(crate) fn subquery_placeholder(i: usize) -> String {
+    format!("{}{}", SUBQUERY_PLACEHOLDER, i)
+}
+
+/// a window function, which is referred to by a placeholder column named by
+/// `window_placeholder`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Window {
+    /// evaluated over each partition of rows sorted by `order_by`
+    pub(crate) function: dsl::Expr,
+    pub(crate) partition_by: Vec<dsl::Expr>,
+    pub(crate) order_by: Vec<(dsl::Expr, bool)>,
+}
+
+/// a key of ORDER BY, an ordinal position refers to a select item as `Expr::Nth`
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderBy {
+    pub(crate) expr: dsl::Expr,
+    pub(crate) desc: bool,
+    /// `NULLS FIRST` or `NULLS LAST`, polars' default placement if unspecified
+    pub(crate) nulls_first: Option<bool>,
+}
+
+pub(crate) const WINDOW_PLACEHOLDER: &str = "__sq_window_";
+
+/// a placeholder like `?1`, `$1` or `:name` is referred to by a column named by
+/// `param_placeholder` until it's bound
+pub(crate) const PARAM_PLACEHOLDER: &str = "__sq_param_";
+
+pub(crate) fn param_placeholder(placeholder: &str) -> String {
+    format!("{}{}", PARAM_PLACEHOLDER, placeholder)
+}
+
+pub(crate) fn window_placeholder(i: usize) -> String {
+    format!("{}{}", WINDOW_PLACEHOLDER, i)
+}
+
+/// subqueries and window functions in expressions of a select
+#[derive(Debug, Default)]
+struct Collected {
+    subqueries: Vec<Subquery>,
+    windows: Vec<Window>,
+}
+
+thread_local! {
+    /// collected while converting expressions of the selects being converted,
+    /// one frame per select
+    static COLLECTED: RefCell<Vec<Collected>> = const { RefCell::new(vec![]) };
+}
+
+/// run `f` in a new frame, and return the subqueries and windows collected
+fn collect<T>(f: impl FnOnce() -> Result<T, SqError>) -> Result<(T, Collected), SqError> {
+    COLLECTED.with(|s| s.borrow_mut().push(Collected::default()));
+    let res = f();
+    let collected = COLLECTED.with(|s| s.borrow_mut().pop()).unwrap_or_default();
+    res.map(|res| (res, collected))
+}
+
+fn push_subquery(query: &ast::Query, kind: SubqueryKind) -> Result<dsl::Expr, SqError> {
+    let query = SqlSelect(query).try_into()?;
+    COLLECTED.with(|s| match s.borrow_mut().last_mut() {
+        Some(frame) => {
+            frame.subqueries.push(Subquery { query, kind });
+            Ok(dsl::col(&subquery_placeholder(frame.subqueries.len() - 1)))
+        }
+        None => Err(SqError::AstError("subquery is not allowed here".to_owned())),
+    })
+}
+
+fn push_window(window: Window) -> Result<dsl::Expr, SqError> {
+    COLLECTED.with(|s| match s.borrow_mut().last_mut() {
+        Some(frame) => {
+            frame.windows.push(window);
+            Ok(dsl::col(&window_placeholder(frame.windows.len() - 1)))
+        }
+        None => Err(SqError::AstError("window function is not allowed here".to_owned())),
+    })
+}
+
+/// a common table expression of `WITH`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cte {
+    pub(crate) name: String,
+    pub(crate) columns: Vec<String>,
+    pub(crate) query: Query,
+}
+
 // AST for sql query
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Default)]
 pub struct Query {
+    pub(crate) ctes: Vec<Cte>,
     pub(crate) projections: Vec<dsl::Expr>,
-    pub(crate) source: Option<String>,
+    pub(crate) source: Option<Relation>,
+    pub(crate) joins: Vec<Join>,
     pub(crate) condition: Option<dsl::Expr>,
+    pub(crate) group_by: Vec<dsl::Expr>,
+    pub(crate) having: Option<dsl::Expr>,
     pub(crate) limit: Option<usize>,
     pub(crate) offset: Option<i64>,
-    pub(crate) order_by: Vec<(dsl::Expr, bool)>,
+    pub(crate) order_by: Vec<OrderBy>,
+    /// `DISTINCT` if the keys are empty, otherwise `DISTINCT ON (keys)`
+    pub(crate) distinct: Option<Vec<dsl::Expr>>,
+    /// `* REPLACE (expr AS col)`, aliased expressions taking the place of the
+    /// columns of the same name expanded from the wildcard
+    pub(crate) replace: Vec<dsl::Expr>,
+    pub(crate) subqueries: Vec<Subquery>,
+    pub(crate) windows: Vec<Window>,
+    /// placeholders of the whole statement in order of appearance, bare `?` are
+    /// numbered like `?1`
+    pub(crate) params: Vec<String>,
+}
+
+/// a statement of a script
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Query(Query),
+    /// `CREATE [OR REPLACE] [TEMP] VIEW name [(columns)] AS query`, planned again
+    /// whenever referred to
+    CreateView { view: Cte, or_replace: bool },
+    /// `CREATE [OR REPLACE] [TEMP] TABLE [IF NOT EXISTS] name AS query` or
+    /// `CREATE MATERIALIZED VIEW name AS query`, materialized when created
+    CreateTable { table: Cte, or_replace: bool, if_not_exists: bool },
+    /// `DROP VIEW|TABLE [IF EXISTS] names`
+    Drop { view: bool, names: Vec<String>, if_exists: bool },
+    /// `SET name = value`
+    Set { name: String, value: String },
+    /// `COPY (query)|name TO 'url' [WITH] [(option value, ...)]`, options are
+    /// named in lowercase
+    Copy { query: Query, target: String, options: Vec<(String, String)> },
+    /// `INSERT INTO url|table query`, rows are appended
+    Insert { target: String, query: Query },
+    /// `EXPLAIN [ANALYZE] query`, ANALYZE runs the query and times its stages
+    Explain { query: Query, analyze: bool },
 }
 
+#[derive(Debug)]
+struct SqlStatement<'a>(&'a ast::Statement);
 #[derive(Debug)]
 struct SqlExpression<'a>(&'a ast::Expr);
 #[derive(Debug)]
+struct SqlFunction<'a>(&'a ast::Function);
+#[derive(Debug)]
 struct SqlSelectItem<'a>(&'a ast::SelectItem);
 #[derive(Debug)]
 struct SqlSelect<'a>(&'a ast::Query);
 #[derive(Debug)]
+struct SqlSetExpr<'a>(&'a ast::SetExpr);
+#[derive(Debug)]
+struct SqlRelation<'a>(&'a ast::TableFactor);
+#[derive(Debug)]
+struct SqlJoin<'a>(&'a ast::Join);
+#[derive(Debug)]
 struct SqlBinOp<'a>(&'a ast::BinaryOperator);
 #[derive(Debug)]
 struct SqlValue<'a>(&'a ast::Value);
@@ -74,6 +273,174 @@ struct SqlLimit<'a>(&'a ast::Expr);
 struct SqlOffset<'a>(&'a ast::Offset);
 #[derive(Debug)]
 struct SqlOrderBy<'a>(&'a ast::OrderByExpr);
+#[derive(Debug)]
+struct SqlDataType<'a>(&'a ast::DataType);
+#[derive(Debug)]
+struct SqlWindowFrame<'a>(&'a ast::WindowFrame);
+
+impl Query {
+    /// urls of all the tables the query reads from
+    pub(crate) fn sources(&self) -> Vec<&str> {
+        self.sources_with(&[])
+    }
+
+    /// urls of tables, excluding references to the visible CTEs
+    fn sources_with<'a>(&'a self, ctes: &[&'a str]) -> Vec<&'a str> {
+        let mut visible = ctes.to_vec();
+        let mut res = vec![];
+        for cte in self.ctes.iter() {
+            res.extend(cte.query.sources_with(&visible));
+            visible.push(cte.name.as_str());
+        }
+
+        for r in self.source.iter().chain(self.joins.iter().map(|j| &j.relation)) {
+            match r {
+                Relation::Table { name, .. } if !visible.contains(&name.as_str()) => res.push(name.as_str()),
+                Relation::Table { .. } => {}
+                Relation::Derived { query, .. } => res.extend(query.sources_with(&visible)),
+                Relation::SetOperation { left, right, .. } => {
+                    res.extend(left.sources_with(&visible));
+                    res.extend(right.sources_with(&visible));
+                }
+            }
+        }
+        res.extend(self.subqueries.iter().flat_map(|s| s.query.sources_with(&visible)));
+        res
+    }
+
+    /// substitute the values of `params` for the placeholders, including those
+    /// in place of a table
+    pub(crate) fn bind(&mut self, params: &Params) -> Result<(), SqError> {
+        self.try_for_each_query(&mut |query| {
+            for r in query.source.iter_mut().chain(query.joins.iter_mut().map(|j| &mut j.relation)) {
+                if let Relation::Table { name, .. } = r {
+                    if let Some(url) = params.source(name)? {
+                        *name = url;
+                    }
+                }
+            }
+            query.exprs_mut().try_for_each(|e| params.bind_expr(e))
+        })
+    }
+
+    /// substitute the time the query is run at for `now()` and the like
+    pub(crate) fn bind_now(&mut self, now: NaiveDateTime) -> Result<(), SqError> {
+        let now = dsl::Expr::Literal(LiteralValue::DateTime(now, TimeUnit::Microseconds));
+        self.try_for_each_query(&mut |query| {
+            for e in query.exprs_mut() {
+                e.mutate().apply(|e| {
+                    if matches!(e, dsl::Expr::Column(name) if name.as_ref() == functions::NOW_PLACEHOLDER) {
+                        *e = now.clone();
+                    }
+                    true
+                });
+            }
+            Ok(())
+        })
+    }
+
+    /// call `f` on this query and on each query nested in it, its CTEs, derived
+    /// tables, set operations and subqueries
+    fn try_for_each_query(&mut self, f: &mut dyn FnMut(&mut Query) -> Result<(), SqError>) -> Result<(), SqError> {
+        for cte in self.ctes.iter_mut() {
+            cte.query.try_for_each_query(f)?;
+        }
+        for r in self.source.iter_mut().chain(self.joins.iter_mut().map(|j| &mut j.relation)) {
+            match r {
+                Relation::Table { .. } => {}
+                Relation::Derived { query, .. } => query.try_for_each_query(f)?,
+                Relation::SetOperation { left, right, .. } => {
+                    left.try_for_each_query(f)?;
+                    right.try_for_each_query(f)?;
+                }
+            }
+        }
+        for s in self.subqueries.iter_mut() {
+            s.query.try_for_each_query(f)?;
+        }
+        f(self)
+    }
+
+    /// the expressions of this query, not of the queries nested in it
+    fn exprs_mut(&mut self) -> impl Iterator<Item = &mut dsl::Expr> {
+        self.projections
+            .iter_mut()
+            .chain(self.joins.iter_mut().filter_map(|j| match &mut j.constraint {
+                JoinConstraint::On(e) => Some(e),
+                _ => None,
+            }))
+            .chain(self.condition.iter_mut())
+            .chain(self.group_by.iter_mut())
+            .chain(self.having.iter_mut())
+            .chain(self.order_by.iter_mut().map(|o| &mut o.expr))
+            .chain(self.distinct.iter_mut().flatten())
+            .chain(self.replace.iter_mut())
+            .chain(self.subqueries.iter_mut().filter_map(|s| match &mut s.kind {
+                SubqueryKind::In { expr, .. } => Some(expr),
+                _ => None,
+            }))
+            .chain(self.windows.iter_mut().flat_map(|w| {
+                std::iter::once(&mut w.function)
+                    .chain(w.partition_by.iter_mut())
+                    .chain(w.order_by.iter_mut().map(|(e, _)| e))
+            }))
+    }
+
+    /// the clauses of the query that are present, described for EXPLAIN
+    pub(crate) fn describe(&self) -> Vec<(&'static str, String)> {
+        let mut res = vec![];
+        let mut push = |name, present: bool, value: String| {
+            if present {
+                res.push((name, value));
+            }
+        };
+        push("with", !self.ctes.is_empty(), format!("{:?}", self.ctes.iter().map(|c| &c.name).collect::<Vec<_>>()));
+        push("projections", true, format!("{:?}", self.projections));
+        push("source", self.source.is_some(), format!("{:?}", self.source));
+        push("joins", !self.joins.is_empty(), format!("{:?}", self.joins));
+        push("condition", self.condition.is_some(), format!("{:?}", self.condition));
+        push("group_by", !self.group_by.is_empty(), format!("{:?}", self.group_by));
+        push("having", self.having.is_some(), format!("{:?}", self.having));
+        push("windows", !self.windows.is_empty(), format!("{:?}", self.windows));
+        push("order_by", !self.order_by.is_empty(), format!("{:?}", self.order_by));
+        push("distinct", self.distinct.is_some(), format!("{:?}", self.distinct));
+        push("limit", self.limit.is_some(), format!("{:?}", self.limit));
+        push("offset", self.offset.is_some(), format!("{:?}", self.offset));
+        push("subqueries", !self.subqueries.is_empty(), format!("{}", self.subqueries.len()));
+        res
+    }
+
+    /// `select * from relation`
+    fn select_all(relation: Relation) -> Self {
+        Query { projections: vec![dsl::Expr::Wildcard], source: Some(relation), ..Default::default() }
+    }
+
+    /// whether the query needs an aggregation stage, either by explicit `GROUP BY`
+    /// or by aggregate functions over the whole table
+    pub(crate) fn is_aggregated(&self) -> bool {
+        !self.group_by.is_empty() || self.having.is_some() || self.projections.iter().any(has_aggregation)
+    }
+}
+
+pub(crate) fn has_aggregation(e: &dsl::Expr) -> bool {
+    e.into_iter().any(|e| matches!(e, dsl::Expr::Agg(_) | dsl::Expr::Count))
+}
+
+/// strip the alias of a select item, if any
+pub(crate) fn unaliased(e: &dsl::Expr) -> &dsl::Expr {
+    match e {
+        dsl::Expr::Alias(e, _) => e.as_ref(),
+        _ => e,
+    }
+}
+
+/// name of the column an expression produces, following polars' naming rule
+pub(crate) fn output_name(e: &dsl::Expr) -> Option<Arc<str>> {
+    e.into_iter().find_map(|e| match e {
+        dsl::Expr::Alias(_, name) | dsl::Expr::Column(name) => Some(name.clone()),
+        _ => None,
+    })
+}
 
 impl<'a> TryFrom<SqlValue<'a>> for LiteralValue {
     type Error = SqError;
@@ -81,14 +448,94 @@ impl<'a> TryFrom<SqlValue<'a>> for LiteralValue {
     fn try_from(value: SqlValue<'a>) -> Result<Self, Self::Error> {
         use ast::Value::*;
         match value.0 {
-            Number(s, _b) => Ok(LiteralValue::Float64(s.parse()?)),
+            // integers stay integers unless they overflow
+            Number(s, _b) => match s.parse::<i64>() {
+                Ok(n) => Ok(LiteralValue::Int64(n)),
+                Err(_) => Ok(LiteralValue::Float64(s.parse()?)),
+            },
             Boolean(b) => Ok(LiteralValue::Boolean(*b)),
-            SingleQuotedString(s) => Ok(LiteralValue::Utf8(s.clone())),
-            _ => todo!("SqlValue: {} not supported", value.0),
+            Null => Ok(LiteralValue::Null),
+            SingleQuotedString(s) | DoubleQuotedString(s) | NationalStringLiteral(s) | EscapedStringLiteral(s) => {
+                Ok(LiteralValue::Utf8(s.clone()))
+            }
+            HexStringLiteral(s) => {
+                // each byte takes two digits
+                if s.len() % 2 != 0 {
+                    return Err(SqError::unsupported(value.0));
+                }
+                let bytes = s
+                    .as_bytes()
+                    .chunks(2)
+                    .map(|c| Ok(u8::from_str_radix(std::str::from_utf8(c)?, 16)?))
+                    .collect::<Result<Vec<_>, SqError>>()?;
+                Ok(LiteralValue::Utf8(String::from_utf8(bytes)?))
+            }
+            _ => Err(SqError::unsupported(value.0)),
         }
     }
 }
 
+impl<'a> TryFrom<SqlDataType<'a>> for DataType {
+    type Error = SqError;
+
+    fn try_from(value: SqlDataType<'a>) -> Result<Self, Self::Error> {
+        use ast::DataType as dt;
+        match value.0 {
+            dt::Boolean => Ok(DataType::Boolean),
+            dt::TinyInt(_) => Ok(DataType::Int8),
+            dt::SmallInt(_) => Ok(DataType::Int16),
+            dt::Int(_) | dt::Integer(_) | dt::MediumInt(_) => Ok(DataType::Int32),
+            dt::BigInt(_) => Ok(DataType::Int64),
+            dt::UnsignedTinyInt(_) => Ok(DataType::UInt8),
+            dt::UnsignedSmallInt(_) => Ok(DataType::UInt16),
+            dt::UnsignedInt(_) | dt::UnsignedInteger(_) | dt::UnsignedMediumInt(_) => Ok(DataType::UInt32),
+            dt::UnsignedBigInt(_) => Ok(DataType::UInt64),
+            dt::Real | dt::Float(Some(1..=24)) => Ok(DataType::Float32),
+            dt::Float(_) | dt::Double | dt::DoublePrecision => Ok(DataType::Float64),
+            // polars has no decimal type yet, decimals are approximated by floats
+            dt::Decimal(_) | dt::Numeric(_) | dt::Dec(_) => Ok(DataType::Float64),
+            dt::Char(_) | dt::Character(_) | dt::CharVarying(_) | dt::CharacterVarying(_) | dt::Varchar(_)
+            | dt::Nvarchar(_) | dt::Text | dt::String => Ok(DataType::Utf8),
+            dt::Date => Ok(DataType::Date),
+            dt::Datetime(_) | dt::Timestamp(_, ast::TimezoneInfo::None | ast::TimezoneInfo::WithoutTimeZone) => {
+                Ok(DataType::Datetime(TimeUnit::Microseconds, None))
+            }
+            _ => Err(SqError::unsupported(value.0)),
+        }
+    }
+}
+
+impl<'a> TryFrom<SqlWindowFrame<'a>> for functions::Frame {
+    type Error = SqError;
+
+    fn try_from(value: SqlWindowFrame<'a>) -> Result<Self, Self::Error> {
+        use ast::WindowFrameBound as bound;
+        use functions::Frame;
+
+        let ast::WindowFrame { units, start_bound, end_bound } = value.0;
+        let end_bound = end_bound.as_ref().unwrap_or(&bound::CurrentRow);
+        match (units, start_bound, end_bound) {
+            (ast::WindowFrameUnits::Groups, _, _) => {}
+            (ast::WindowFrameUnits::Range, bound::Preceding(None), bound::CurrentRow) => return Ok(Frame::RunningPeers),
+            (ast::WindowFrameUnits::Range, bound::CurrentRow, bound::Following(None)) => return Ok(Frame::RemainingPeers),
+            (_, bound::Preceding(None), bound::CurrentRow) => return Ok(Frame::Running),
+            (_, bound::Preceding(None), bound::Following(None)) => return Ok(Frame::Partition),
+            (_, bound::CurrentRow, bound::Following(None)) => return Ok(Frame::Remaining),
+            (ast::WindowFrameUnits::Rows, bound::CurrentRow, bound::CurrentRow) => return Ok(Frame::Preceding(0)),
+            (ast::WindowFrameUnits::Rows, bound::Preceding(Some(n)), bound::CurrentRow) => {
+                if let ast::Expr::Value(ast::Value::Number(n, _)) = n.as_ref() {
+                    return Ok(Frame::Preceding(n.parse()?));
+                }
+            }
+            _ => {}
+        }
+        Err(SqError::unsupported(match value.0.end_bound {
+            Some(ref end) => format!("{} BETWEEN {} AND {}", units, start_bound, end),
+            None => format!("{} {}", units, start_bound),
+        }))
+    }
+}
+
 impl<'a> TryFrom<SqlBinOp<'a>> for dsl::Operator {
     type Error = SqError;
 
@@ -109,7 +556,7 @@ impl<'a> TryFrom<SqlBinOp<'a>> for dsl::Operator {
             op::And => Ok(Self::And),
             op::Or => Ok(Self::Or),
             op::Xor => Ok(Self::Xor),
-            _ => Err(SqError::AstError(format!("SqlBinOp {} is not supported", value.0))),
+            _ => Err(SqError::unsupported(value.0)),
         }
     }
 }
@@ -150,15 +597,231 @@ impl<'a> TryFrom<SqlExpression<'a>> for dsl::Expr {
 
     fn try_from(value: SqlExpression<'a>) -> Result<Self, Self::Error> {
         match value.0 {
-            ast::Expr::BinaryOp { left, op, right } => Ok(Self::BinaryExpr { 
-                left: Box::new(SqlExpression(left).try_into()?),
-                op: SqlBinOp(op).try_into()?,
-                right: Box::new(SqlExpression(right).try_into()?), 
-            }),
+            ast::Expr::BinaryOp { left, op, right } => {
+                use ast::BinaryOperator as op;
+                let l: dsl::Expr = SqlExpression(left).try_into()?;
+                let r: dsl::Expr = SqlExpression(right).try_into()?;
+                match op {
+                    op::StringConcat => Ok(dsl::concat_str([l.cast(DataType::Utf8), r.cast(DataType::Utf8)], "")),
+                    op::PGRegexMatch => functions::regex_match(l, &r, false),
+                    op::PGRegexIMatch => functions::regex_match(l, &r, true),
+                    op::PGRegexNotMatch => Ok(functions::regex_match(l, &r, false)?.not()),
+                    op::PGRegexNotIMatch => Ok(functions::regex_match(l, &r, true)?.not()),
+                    _ => Ok(Self::BinaryExpr { left: Box::new(l), op: SqlBinOp(op).try_into()?, right: Box::new(r) }),
+                }
+            }
+            ast::Expr::UnaryOp { op, expr } => {
+                let e: dsl::Expr = SqlExpression(expr).try_into()?;
+                match (op, e) {
+                    (ast::UnaryOperator::Not, e) => Ok(e.not()),
+                    (ast::UnaryOperator::Plus, e) => Ok(e),
+                    (ast::UnaryOperator::Minus, dsl::Expr::Literal(LiteralValue::Float64(f))) => Ok(dsl::lit(-f)),
+                    (ast::UnaryOperator::Minus, dsl::Expr::Literal(LiteralValue::Int64(n))) => Ok(dsl::lit(-n)),
+                    (ast::UnaryOperator::Minus, dsl::Expr::Literal(LiteralValue::Int32(n))) => Ok(dsl::lit(-n)),
+                    (ast::UnaryOperator::Minus, e) => Ok(dsl::lit(0) - e),
+                    _ => Err(SqError::unsupported(value.0)),
+                }
+            }
+            ast::Expr::IsNull(e) => Ok(dsl::Expr::try_from(SqlExpression(e))?.is_null()),
+            ast::Expr::IsNotNull(e) => Ok(dsl::Expr::try_from(SqlExpression(e))?.is_not_null()),
+            ast::Expr::InList { expr, list, negated } => {
+                let e: dsl::Expr = SqlExpression(expr).try_into()?;
+                let mut res = None;
+                for item in list {
+                    let eq = e.clone().eq(dsl::Expr::try_from(SqlExpression(item))?);
+                    res = Some(match res {
+                        Some(res) => dsl::Expr::or(res, eq),
+                        None => eq,
+                    });
+                }
+                let res = res.ok_or_else(|| SqError::AstError(format!("SqlExpression: {} is invalid", value.0)))?;
+                Ok(if *negated { res.not() } else { res })
+            }
+            ast::Expr::Between { expr, negated, low, high } => {
+                let e: dsl::Expr = SqlExpression(expr).try_into()?;
+                let low: dsl::Expr = SqlExpression(low).try_into()?;
+                let high: dsl::Expr = SqlExpression(high).try_into()?;
+                let res = e.clone().gt_eq(low).and(e.lt_eq(high));
+                Ok(if *negated { res.not() } else { res })
+            }
+            ast::Expr::Like { negated, expr, pattern, escape_char } | ast::Expr::ILike { negated, expr, pattern, escape_char } => {
+                let ilike = matches!(value.0, ast::Expr::ILike { .. });
+                let res = functions::like(SqlExpression(expr).try_into()?, &SqlExpression(pattern).try_into()?, *escape_char, ilike)?;
+                Ok(if *negated { res.not() } else { res })
+            }
             ast::Expr::Identifier(id) => Ok(Self::Column(Arc::from(id.value.as_str()))),
+            ast::Expr::CompoundIdentifier(ids) => Ok(Self::Column(Arc::from(
+                ids.iter().map(|id| id.value.as_str()).collect::<Vec<_>>().join(".").as_str()))),
+            ast::Expr::Value(ast::Value::Placeholder(p)) => Ok(dsl::col(&param_placeholder(p))),
             ast::Expr::Value(v) => Ok(Self::Literal(SqlValue(v).try_into()?)),
+            ast::Expr::Function(f) => SqlFunction(f).try_into(),
+            ast::Expr::Nested(e) => SqlExpression(e).try_into(),
+            ast::Expr::Subquery(q) => push_subquery(q, SubqueryKind::Scalar),
+            ast::Expr::InSubquery { expr, subquery, negated } => {
+                let expr = SqlExpression(expr).try_into()?;
+                push_subquery(subquery, SubqueryKind::In { expr, negated: *negated })
+            }
+            ast::Expr::Exists { subquery, negated } => push_subquery(subquery, SubqueryKind::Exists { negated: *negated }),
+            ast::Expr::Extract { field, expr } => {
+                functions::date_part(&field.to_string(), SqlExpression(expr).try_into()?).map_err(|e| e.within(value.0))
+            }
+            ast::Expr::AtTimeZone { timestamp, time_zone } => {
+                functions::at_time_zone(SqlExpression(timestamp).try_into()?, time_zone)
+            }
+            ast::Expr::TypedString { data_type, value } => functions::typed_literal(SqlDataType(data_type).try_into()?, value),
+            ast::Expr::Interval { value: interval, leading_field, last_field: None, .. } => {
+                let s = match interval.as_ref() {
+                    ast::Expr::Value(ast::Value::SingleQuotedString(s)) => s.clone(),
+                    ast::Expr::Value(ast::Value::Number(n, _)) => n.clone(),
+                    e => return Err(SqError::AstError(format!("INTERVAL {} must be a literal", e))),
+                };
+                let unit = leading_field.as_ref().map(|f| f.to_string());
+                Ok(dsl::Expr::Literal(functions::interval(&s, unit.as_deref()).map_err(|e| e.within(value.0))?))
+            }
+            // CAST fails on values that can't be converted, while TRY_CAST turns them into NULLs
+            ast::Expr::Cast { expr, data_type } => {
+                Ok(functions::cast(SqlExpression(expr).try_into()?, SqlDataType(data_type).try_into()?, true))
+            }
+            ast::Expr::TryCast { expr, data_type } | ast::Expr::SafeCast { expr, data_type } => {
+                Ok(functions::cast(SqlExpression(expr).try_into()?, SqlDataType(data_type).try_into()?, false))
+            }
+            ast::Expr::Case { operand, conditions, results, else_result } => {
+                let operand: Option<dsl::Expr> = operand.as_ref().map(|e| SqlExpression(e).try_into()).transpose()?;
+                let mut otherwise = match else_result {
+                    Some(e) => SqlExpression(e).try_into()?,
+                    None => dsl::lit(polars::prelude::NULL),
+                };
+                for (c, r) in conditions.iter().zip(results.iter()).rev() {
+                    let c: dsl::Expr = SqlExpression(c).try_into()?;
+                    let c = match &operand {
+                        Some(operand) => operand.clone().eq(c),
+                        None => c,
+                    };
+                    otherwise = dsl::when(c).then(dsl::Expr::try_from(SqlExpression(r))?).otherwise(otherwise);
+                }
+                Ok(otherwise)
+            }
+            // functions with special syntax
+            ast::Expr::Trim { expr, trim_where, trim_what } => {
+                let name = match trim_where {
+                    None | Some(ast::TrimWhereField::Both) => "trim",
+                    Some(ast::TrimWhereField::Leading) => "ltrim",
+                    Some(ast::TrimWhereField::Trailing) => "rtrim",
+                };
+                let mut args = vec![SqlExpression(expr).try_into()?];
+                if let Some(what) = trim_what {
+                    args.push(SqlExpression(what).try_into()?);
+                }
+                functions::scalar(name, args)
+            }
+            ast::Expr::Substring { expr, substring_from, substring_for } => {
+                let mut args = vec![SqlExpression(expr).try_into()?];
+                args.push(match substring_from {
+                    Some(e) => SqlExpression(e).try_into()?,
+                    None => dsl::lit(1),
+                });
+                if let Some(e) = substring_for {
+                    args.push(SqlExpression(e).try_into()?);
+                }
+                functions::scalar("substr", args)
+            }
+            ast::Expr::Ceil { expr, field: ast::DateTimeField::NoDateTime } => {
+                functions::scalar("ceil", vec![SqlExpression(expr).try_into()?])
+            }
+            ast::Expr::Floor { expr, field: ast::DateTimeField::NoDateTime } => {
+                functions::scalar("floor", vec![SqlExpression(expr).try_into()?])
+            }
+
+            _ => Err(SqError::unsupported(value.0)),
+        }
+    }
+}
+
+const AGGREGATES: &[&str] = &[
+    "count", "sum", "avg", "mean", "min", "max", "first", "last", "median",
+    "stddev", "stddev_samp", "std", "stddev_pop", "variance", "var_samp", "var", "var_pop",
+];
+
+impl<'a> TryFrom<SqlFunction<'a>> for dsl::Expr {
+    type Error = SqError;
+
+    fn try_from(value: SqlFunction<'a>) -> Result<Self, Self::Error> {
+        let ast::Function { name, args, over, distinct, .. } = value.0;
+        let name = name.to_string().to_lowercase();
+        let args = args
+            .iter()
+            .map(|arg| match arg {
+                ast::FunctionArg::Unnamed(arg) => Ok(arg),
+                _ => Err(SqError::unsupported(value.0)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
 
-            _ => Err(SqError::AstError(format!("SqlExpression: {} not supported", value.0))),
+        if let Some(over) = over {
+            if *distinct {
+                return Err(SqError::unsupported(value.0));
+            }
+            let args = match (name.as_str(), args.as_slice()) {
+                // count(*) counts all the rows
+                ("count", [ast::FunctionArgExpr::Wildcard]) => vec![],
+                _ => args
+                    .iter()
+                    .map(|arg| match arg {
+                        ast::FunctionArgExpr::Expr(arg) => SqlExpression(arg).try_into(),
+                        _ => Err(SqError::unsupported(value.0)),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+            };
+            let mut partition_by = vec![];
+            for e in over.partition_by.iter() {
+                partition_by.push(SqlExpression(e).try_into()?);
+            }
+            let mut order_by: Vec<(dsl::Expr, bool)> = vec![];
+            for e in over.order_by.iter() {
+                order_by.push(SqlOrderBy(e).try_into()?);
+            }
+            let frame = match &over.window_frame {
+                Some(frame) => SqlWindowFrame(frame).try_into()?,
+                None if order_by.is_empty() => functions::Frame::Partition,
+                None => functions::Frame::RunningPeers,
+            };
+            let keys = order_by.iter().map(|(e, _)| e.clone()).collect::<Vec<_>>();
+            let function = functions::window(&name, args, &keys, frame).map_err(|e| e.within(value.0))?;
+            return push_window(Window { function, partition_by, order_by });
+        }
+
+        match (name.as_str(), args.as_slice()) {
+            ("count", [ast::FunctionArgExpr::Wildcard]) if !distinct => Ok(dsl::count()),
+            (_, [ast::FunctionArgExpr::Expr(arg)]) if AGGREGATES.contains(&name.as_str()) => {
+                let arg: dsl::Expr = SqlExpression(arg).try_into()?;
+                let arg = if *distinct { arg.unique() } else { arg };
+                match name.as_str() {
+                    "count" => Ok(arg.drop_nulls().count()),
+                    "sum" => Ok(arg.sum()),
+                    "avg" | "mean" => Ok(arg.mean()),
+                    "min" => Ok(arg.min()),
+                    "max" => Ok(arg.max()),
+                    "first" => Ok(arg.first()),
+                    "last" => Ok(arg.last()),
+                    "median" => Ok(arg.median()),
+                    "stddev" | "stddev_samp" | "std" => Ok(arg.std(1)),
+                    "stddev_pop" => Ok(arg.std(0)),
+                    "variance" | "var_samp" | "var" => Ok(arg.var(1)),
+                    _ => Ok(arg.var(0)),
+                }
+            }
+            _ if AGGREGATES.contains(&name.as_str()) || *distinct => {
+                Err(SqError::unsupported(value.0))
+            }
+            _ => {
+                let args = args
+                    .iter()
+                    .map(|arg| match arg {
+                        ast::FunctionArgExpr::Expr(arg) => SqlExpression(arg).try_into(),
+                        _ => Err(SqError::unsupported(value.0)),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                functions::scalar(&name, args).map_err(|e| e.within(value.0))
+            }
         }
     }
 }
@@ -170,84 +833,860 @@ impl<'a> TryFrom<SqlSelectItem<'a>> for dsl::Expr {
         use ast::SelectItem::*;
         match value.0 {
             Wildcard => Ok(Self::Wildcard),
-            ExprWithAlias { expr: ast::Expr::Identifier(id), alias } => {
-                Ok(Self::Column(Arc::from(id.value.as_str())).alias(alias.value.as_str()))
+            QualifiedWildcard(name) => Ok(dsl::col(&format!("{}.*", name))),
+            ExprWithAlias { expr, alias } => {
+                Ok(dsl::Expr::try_from(SqlExpression(expr))?.alias(alias.value.as_str()))
             },
             UnnamedExpr(ast::Expr::Identifier(id)) => 
                 Ok(Self::Column(Arc::from(id.value.as_str()))),
-            UnnamedExpr(ast::Expr::Value(value)) => 
+            UnnamedExpr(expr @ ast::Expr::CompoundIdentifier(_)) => SqlExpression(expr).try_into(),
+            UnnamedExpr(ast::Expr::Value(value)) if !matches!(value, ast::Value::Placeholder(_)) =>
                 Ok(Self::Literal(SqlValue(value).try_into()?)),
-            _ => todo!("SelectItem: {} is not supported", value.0)
+            // other expressions are named by their sql text
+            UnnamedExpr(expr) => {
+                Ok(dsl::Expr::try_from(SqlExpression(expr))?.alias(&expr.to_string()))
+            },
         }
     }
 }
 
-impl<'a> TryFrom<SqlSelect<'a>> for Query {
+const SEMI_JOIN_MARKER: &str = "__sq_semi_join";
+const ANTI_JOIN_MARKER: &str = "__sq_anti_join";
+
+impl<'a> TryFrom<SqlRelation<'a>> for Relation {
     type Error = SqError;
 
-    fn try_from(value: SqlSelect<'a>) -> Result<Self, Self::Error> {
-        let query = value.0;
-        println!("{:#?}", query);
+    fn try_from(value: SqlRelation<'a>) -> Result<Self, Self::Error> {
+        match value.0 {
+            ast::TableFactor::Table { name, alias, .. } => {
+                let idents = match name.0.first() {
+                    Some(id) if id.value == SEMI_JOIN_MARKER || id.value == ANTI_JOIN_MARKER => &name.0[1..],
+                    _ => &name.0[..],
+                };
+                let name = if idents.len() > 1 {
+                    ast::ObjectName(idents.to_vec()).to_string()
+                } else {
+                    idents.iter().map(|id|id.value.as_str()).collect::<Vec<_>>().join("")
+                };
+                Ok(Relation::Table { name, alias: alias.as_ref().map(|a| a.name.value.clone()) })
+            }
+            ast::TableFactor::Derived { lateral: false, subquery, alias } => Ok(Relation::Derived {
+                query: Box::new(SqlSelect(subquery).try_into()?),
+                alias: alias.as_ref().map(|a| a.name.value.clone()),
+                columns: alias.iter().flat_map(|a| a.columns.iter().map(|c| c.value.clone())).collect(),
+            }),
+            _ => Err(SqError::unsupported(value.0)),
+        }
+    }
+}
 
-        if let ast::SetExpr::Select(ref select) = query.body.as_ref() {
-            let source = if let Some(ast::TableWithJoins {relation: ast::TableFactor::Table { name, .. }, .. }) = &select.from.first() {
-                Some(if name.0.len() > 1 {
-                    name.to_string()
+impl<'a> TryFrom<SqlJoin<'a>> for Join {
+    type Error = SqError;
+
+    fn try_from(value: SqlJoin<'a>) -> Result<Self, Self::Error> {
+        use ast::JoinOperator as op;
+        let ast::Join { relation, join_operator } = value.0;
+
+        let (kind, constraint) = match join_operator {
+            op::Inner(c) => {
+                let marker = match relation {
+                    ast::TableFactor::Table { name, .. } => name.0.first().map(|id| id.value.as_str()),
+                    _ => None,
+                };
+                match marker {
+                    Some(SEMI_JOIN_MARKER) => (JoinKind::Semi, c),
+                    Some(ANTI_JOIN_MARKER) => (JoinKind::Anti, c),
+                    _ => (JoinKind::Inner, c),
+                }
+            }
+            op::LeftOuter(c) => (JoinKind::Left, c),
+            op::RightOuter(c) => (JoinKind::Right, c),
+            op::FullOuter(c) => (JoinKind::Full, c),
+            op::CrossJoin => (JoinKind::Cross, &ast::JoinConstraint::None),
+            _ => return Err(SqError::unsupported(value.0)),
+        };
+
+        let constraint = match constraint {
+            ast::JoinConstraint::On(e) => JoinConstraint::On(SqlExpression(e).try_into()?),
+            ast::JoinConstraint::Using(ids) => JoinConstraint::Using(ids.iter().map(|id| id.value.clone()).collect()),
+            ast::JoinConstraint::None => JoinConstraint::None,
+            ast::JoinConstraint::Natural => {
+                return Err(SqError::unsupported(value.0))
+            }
+        };
+        if (kind == JoinKind::Cross) != (constraint == JoinConstraint::None) {
+            return Err(SqError::AstError(format!("SqlJoin: {} is invalid", value.0)));
+        }
+
+        Ok(Join {
+            relation: SqlRelation(relation).try_into()?,
+            kind,
+            constraint,
+        })
+    }
+}
+
+fn object_name(name: &ast::ObjectName) -> String {
+    name.0.iter().map(|id| id.value.as_str()).collect::<Vec<_>>().join(".")
+}
+
+impl<'a> TryFrom<SqlStatement<'a>> for Statement {
+    type Error = SqError;
+
+    fn try_from(value: SqlStatement<'a>) -> Result<Self, Self::Error> {
+        match value.0 {
+            ast::Statement::Query(query) => Ok(Statement::Query(SqlSelect(query).try_into()?)),
+            ast::Statement::CreateView { or_replace, materialized, name, columns, query, with_options }
+                if with_options.is_empty() =>
+            {
+                let cte = Cte {
+                    name: object_name(name),
+                    columns: columns.iter().map(|c| c.value.clone()).collect(),
+                    query: SqlSelect(query).try_into()?,
+                };
+                Ok(if *materialized {
+                    Statement::CreateTable { table: cte, or_replace: *or_replace, if_not_exists: false }
                 } else {
-                    name.0.iter().map(|id|id.value.as_str()).collect::<Vec<_>>().join("")
+                    Statement::CreateView { view: cte, or_replace: *or_replace }
                 })
-            } else {
-                None
-            };
+            }
+            // every table of a session is temporary
+            ast::Statement::CreateTable {
+                or_replace,
+                external: false,
+                if_not_exists,
+                name,
+                columns,
+                constraints,
+                query: Some(query),
+                ..
+            } if columns.is_empty() && constraints.is_empty() => Ok(Statement::CreateTable {
+                table: Cte { name: object_name(name), columns: vec![], query: SqlSelect(query).try_into()? },
+                or_replace: *or_replace,
+                if_not_exists: *if_not_exists,
+            }),
+            ast::Statement::Drop { object_type, if_exists, names, cascade: false, purge: false, .. }
+                if matches!(object_type, ast::ObjectType::Table | ast::ObjectType::View) =>
+            {
+                Ok(Statement::Drop {
+                    view: *object_type == ast::ObjectType::View,
+                    names: names.iter().map(object_name).collect(),
+                    if_exists: *if_exists,
+                })
+            }
+            ast::Statement::Insert {
+                or: None,
+                into: true,
+                table_name,
+                columns,
+                overwrite: false,
+                source,
+                partitioned: None,
+                table: false,
+                on: None,
+                returning: None,
+                ..
+            } if columns.is_empty() => Ok(Statement::Insert {
+                target: object_name(table_name),
+                query: SqlSelect(source).try_into()?,
+            }),
+            ast::Statement::Explain { describe_alias: false, analyze, statement, format: None, .. } => match statement.as_ref() {
+                ast::Statement::Query(query) => Ok(Statement::Explain { query: SqlSelect(query).try_into()?, analyze: *analyze }),
+                statement => Err(SqError::unsupported(statement)),
+            },
+            ast::Statement::SetVariable { local: false, hivevar: false, variable, value } if value.len() == 1 => {
+                let value = match &value[0] {
+                    ast::Expr::Value(ast::Value::SingleQuotedString(s) | ast::Value::Number(s, _)) => s.clone(),
+                    ast::Expr::Identifier(id) => id.value.clone(),
+                    e => return Err(SqError::unsupported(e)),
+                };
+                Ok(Statement::Set { name: object_name(variable).to_lowercase(), value })
+            }
+            statement => Err(SqError::unsupported(statement)),
+        }
+    }
+}
 
-            let mut projections = vec![];
-            for sel in select.projection.iter() {
-                projections.push(SqlSelectItem(sel).try_into()?);
+impl<'a> TryFrom<SqlSelect<'a>> for Query {
+    type Error = SqError;
+
+    fn try_from(value: SqlSelect<'a>) -> Result<Self, Self::Error> {
+        let query = value.0;
+        if let Some(fetch) = &query.fetch {
+            return Err(SqError::unsupported(fetch));
+        }
+        if let Some(lock) = &query.lock {
+            return Err(SqError::unsupported(lock));
+        }
+
+        let mut ctes = vec![];
+        if let Some(with) = &query.with {
+            if with.recursive {
+                return Err(SqError::unsupported(with));
             }
-            let condition = match select.selection {
-                Some(ref c) => Some(SqlExpression(c).try_into()?),
-                None => None,
-            };
+            for cte in with.cte_tables.iter() {
+                ctes.push(Cte {
+                    name: cte.alias.name.value.clone(),
+                    columns: cte.alias.columns.iter().map(|c| c.value.clone()).collect(),
+                    query: SqlSelect(&cte.query).try_into()?,
+                });
+            }
+        }
 
-            let limit = match query.limit {
+        // ORDER BY and LIMIT of a set operation apply to the combined result, which
+        // is selected from as a whole
+        let (query, collected) = collect(|| {
+            let mut q = match query.body.as_ref() {
+                ast::SetExpr::Select(select) => convert_select(select)?,
+                body => Query::select_all(SqlSetExpr(body).try_into()?),
+            };
+            q.limit = match query.limit {
                 Some(ref e) => Some(SqlLimit(e).try_into()?),
                 None => None,
             };
-            let offset = match query.offset {
+            q.offset = match query.offset {
                 Some(ref e) => Some(SqlOffset(e).try_into()?),
                 None => None,
             };
-            let mut order_by = vec![];
             for e in query.order_by.iter() {
-                order_by.push(SqlOrderBy(e).try_into()?);
+                let (expr, desc) = match &e.expr {
+                    ast::Expr::Value(ast::Value::Number(n, _)) => {
+                        let pos = n.parse::<i64>().ok().filter(|pos| *pos > 0).ok_or_else(|| {
+                            SqError::AstError(format!("ORDER BY position {} is invalid", n))
+                        })?;
+                        (dsl::Expr::Nth(pos - 1), !e.asc.unwrap_or(true))
+                    }
+                    _ => SqlOrderBy(e).try_into()?,
+                };
+                q.order_by.push(OrderBy { expr, desc, nulls_first: e.nulls_first });
             }
+            Ok(q)
+        })?;
+        let query = Query { ctes, subqueries: collected.subqueries, windows: collected.windows, ..query };
+        check_grouping(&query)?;
+        Ok(query)
+    }
+}
+
+impl<'a> TryFrom<SqlSetExpr<'a>> for Relation {
+    type Error = SqError;
+
+    fn try_from(value: SqlSetExpr<'a>) -> Result<Self, Self::Error> {
+        match value.0 {
+            ast::SetExpr::SetOperation { op, set_quantifier, left, right } => Ok(Relation::SetOperation {
+                op: match op {
+                    ast::SetOperator::Union => SetOperator::Union,
+                    ast::SetOperator::Intersect => SetOperator::Intersect,
+                    ast::SetOperator::Except => SetOperator::Except,
+                },
+                all: *set_quantifier == ast::SetQuantifier::All,
+                left: Box::new(SqlSetExpr(left).try_into()?),
+                right: Box::new(SqlSetExpr(right).try_into()?),
+            }),
+            ast::SetExpr::Query(q) => Ok(Relation::Derived {
+                query: Box::new(SqlSelect(q).try_into()?),
+                alias: None,
+                columns: vec![],
+            }),
+            _ => Err(SqError::unsupported(value.0)),
+        }
+    }
+}
 
+/// an operand of a set operation
+impl<'a> TryFrom<SqlSetExpr<'a>> for Query {
+    type Error = SqError;
 
-            Ok(Query {
-                projections,
-                source,
-                condition,
-                limit,
-                offset,
-                order_by,
-            })
+    fn try_from(value: SqlSetExpr<'a>) -> Result<Self, Self::Error> {
+        match value.0 {
+            ast::SetExpr::Select(select) => {
+                let (query, collected) = collect(|| convert_select(select))?;
+                let query = Query { subqueries: collected.subqueries, windows: collected.windows, ..query };
+                check_grouping(&query)?;
+                Ok(query)
+            }
+            ast::SetExpr::Query(q) => SqlSelect(q).try_into(),
+            _ => Ok(Query::select_all(value.try_into()?)),
+        }
+    }
+}
+
+/// projections of an aggregated query must be grouped, aggregated or constant
+fn check_grouping(query: &Query) -> Result<(), SqError> {
+    if query.is_aggregated() && !query.windows.is_empty() {
+        return Err(SqError::AstError("window functions in an aggregated query are not supported".to_owned()));
+    }
+    if query.is_aggregated() {
+        for e in query.projections.iter() {
+            let grouped = query.group_by.iter().any(|k| k == unaliased(e));
+            let constant = !e.into_iter().any(|e| match e {
+                dsl::Expr::Column(name) => !name.starts_with(SUBQUERY_PLACEHOLDER),
+                dsl::Expr::Wildcard => true,
+                _ => false,
+            });
+            if !grouped && !constant && !has_aggregation(e) {
+                return Err(SqError::AstError(format!(
+                    "{} must appear in the GROUP BY clause or be used in an aggregate function", e)));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// convert the clauses of a select, the query level ones like ORDER BY are left
+/// to the caller
+fn convert_select(select: &ast::Select) -> Result<Query, SqError> {
+    // clauses of other dialects, which would change the result if ignored
+    if let Some(top) = &select.top {
+        return Err(SqError::unsupported(top));
+    }
+    if let Some(into) = &select.into {
+        return Err(SqError::unsupported(into));
+    }
+    if let Some(view) = select.lateral_views.first() {
+        return Err(SqError::unsupported(view.to_string().trim()));
+    }
+    for (clause, exprs) in [("CLUSTER BY", &select.cluster_by), ("DISTRIBUTE BY", &select.distribute_by), ("SORT BY", &select.sort_by)] {
+        if let Some(e) = exprs.first() {
+            return Err(SqError::unsupported(format!("{} {}", clause, e)));
+        }
+    }
+    if let Some(qualify) = &select.qualify {
+        return Err(SqError::unsupported(format!("QUALIFY {}", qualify)));
+    }
+
+    let mut source = None;
+    let mut joins = vec![];
+    for (i, from) in select.from.iter().enumerate() {
+        let relation = SqlRelation(&from.relation).try_into()?;
+        if i == 0 {
+            source = Some(relation);
+        } else {
+            // `from a, b` is a cross join
+            joins.push(Join { relation, kind: JoinKind::Cross, constraint: JoinConstraint::None });
+        }
+        for join in from.joins.iter() {
+            joins.push(SqlJoin(join).try_into()?);
+        }
+    }
+
+    let mut items = select.projection.as_slice();
+    let mut distinct = None;
+    if select.distinct {
+        let mut keys = vec![];
+        if let Some(ast::SelectItem::UnnamedExpr(ast::Expr::Function(f))) = items.first() {
+            if f.name.to_string() == DISTINCT_ON_MARKER {
+                for arg in f.args.iter() {
+                    match arg {
+                        ast::FunctionArg::Unnamed(ast::FunctionArgExpr::Expr(e)) => keys.push(SqlExpression(e).try_into()?),
+                        _ => return Err(SqError::AstError(format!("DISTINCT ON {} is invalid", arg))),
+                    }
+                }
+                items = &items[1..];
+            }
+        }
+        distinct = Some(keys);
+    }
+
+    let mut projections = vec![];
+    let mut replace = vec![];
+    for sel in items.iter() {
+        let marker = match sel {
+            ast::SelectItem::UnnamedExpr(ast::Expr::Function(f))
+                if [WILDCARD_EXCEPT_MARKER, WILDCARD_REPLACE_MARKER].contains(&f.name.to_string().as_str()) => f,
+            _ => {
+                projections.push(SqlSelectItem(sel).try_into()?);
+                continue;
+            }
+        };
+
+        let mut args = vec![];
+        for arg in marker.args.iter() {
+            match arg {
+                ast::FunctionArg::Unnamed(ast::FunctionArgExpr::Expr(e)) => args.push(e),
+                _ => return Err(SqError::AstError(format!("{} is invalid in a wildcard option", arg))),
+            }
+        }
+        let wildcard = match projections.pop() {
+            Some(e @ dsl::Expr::Wildcard) => e,
+            Some(dsl::Expr::Column(name)) if name.ends_with(".*") => dsl::Expr::Column(name),
+            Some(e @ dsl::Expr::Exclude(..)) => e,
+            _ => return Err(SqError::AstError("EXCEPT and REPLACE must follow a wildcard".to_owned())),
+        };
+        if marker.name.to_string() == WILDCARD_EXCEPT_MARKER {
+            let (inner, mut excluded) = match wildcard {
+                dsl::Expr::Exclude(inner, excluded) => (inner, excluded),
+                e => (Box::new(e), vec![]),
+            };
+            for e in args {
+                match e {
+                    ast::Expr::Identifier(id) => excluded.push(dsl::Excluded::Name(Arc::from(id.value.as_str()))),
+                    _ => return Err(SqError::AstError(format!("EXCEPT {} is not a column", e))),
+                }
+            }
+            projections.push(dsl::Expr::Exclude(inner, excluded));
+        } else {
+            for pair in args.chunks(2) {
+                match pair {
+                    [e, ast::Expr::Value(ast::Value::SingleQuotedString(name))] => {
+                        replace.push(dsl::Expr::try_from(SqlExpression(e))?.alias(name));
+                    }
+                    _ => return Err(SqError::AstError("REPLACE expects a list of expr AS col".to_owned())),
+                }
+            }
+            projections.push(wildcard);
+        }
+    }
+    let condition = match select.selection {
+        Some(ref c) => Some(SqlExpression(c).try_into()?),
+        None => None,
+    };
+    let mut group_by = vec![];
+    for e in select.group_by.iter() {
+        group_by.push(SqlExpression(e).try_into()?);
+    }
+    let having = match select.having {
+        Some(ref c) => Some(SqlExpression(c).try_into()?),
+        None => None,
+    };
+
+    Ok(Query {
+        projections,
+        source,
+        joins,
+        condition,
+        group_by,
+        having,
+        distinct,
+        replace,
+        ..Default::default()
+    })
+}
+
+/// sqlparser has no syntax for semi and anti joins, so `[LEFT] SEMI JOIN t` and
+/// `[LEFT] ANTI JOIN t` are rewritten into `JOIN <marker>.t` before parsing.
+/// right after a relation, `SEMI` or `ANTI` is its alias instead
+fn rewrite_semi_anti_joins(tokens: Vec<tokenizer::Token>) -> Vec<tokenizer::Token> {
+    use tokenizer::{Token, Word};
+
+    fn word(tok: &Token) -> Option<String> {
+        match tok {
+            Token::Word(Word { value, quote_style: None, .. }) => Some(value.to_uppercase()),
+            _ => None,
+        }
+    }
+
+    // whether `tokens` end with a relation of FROM or JOIN that has no alias, a
+    // name like `file:///tmp/a.csv` split by periods or a derived table
+    fn ends_with_relation(tokens: &[Token]) -> bool {
+        let mut toks = tokens.iter().rev().filter(|t| !matches!(t, Token::Whitespace(_)));
+        match toks.next() {
+            Some(Token::Word(_)) => {}
+            Some(Token::RParen) => {
+                let mut depth = 1;
+                for t in toks.by_ref() {
+                    match t {
+                        Token::LParen if depth == 1 => break,
+                        Token::LParen => depth -= 1,
+                        Token::RParen => depth += 1,
+                        _ => {}
+                    }
+                }
+            }
+            _ => return false,
+        }
+        loop {
+            match toks.next() {
+                Some(Token::Period) => {
+                    if !matches!(toks.next(), Some(Token::Word(_))) {
+                        return false;
+                    }
+                }
+                Some(Token::Comma) => return true,
+                Some(t) => return matches!(word(t).as_deref(), Some("FROM" | "JOIN")),
+                None => return false,
+            }
+        }
+    }
+
+    let mut res: Vec<Token> = Vec::with_capacity(tokens.len());
+    let mut marker = None;
+    for tok in tokens {
+        if matches!(tok, Token::Whitespace(_)) {
+            res.push(tok);
+            continue;
+        }
+
+        if let Some(m) = marker.take() {
+            if word(&tok).is_some() {
+                res.push(Token::make_word(m, None));
+                res.push(Token::Period);
+            }
+            res.push(tok);
+            continue;
+        }
+
+        let kw = word(&tok);
+        let prev = res.iter().rposition(|t| !matches!(t, Token::Whitespace(_)));
+        let prev_kw = prev.and_then(|i| word(&res[i]));
+        match (prev_kw.as_deref(), kw.as_deref()) {
+            (Some(m @ ("SEMI" | "ANTI")), Some("JOIN")) if !ends_with_relation(&res[..prev.unwrap()]) => {
+                marker = Some(if m == "SEMI" { SEMI_JOIN_MARKER } else { ANTI_JOIN_MARKER });
+                let i = prev.unwrap();
+                res.truncate(i);
+                // drop the optional LEFT preceding SEMI/ANTI
+                if let Some(j) = res.iter().rposition(|t| !matches!(t, Token::Whitespace(_))) {
+                    if word(&res[j]).as_deref() == Some("LEFT") {
+                        res.truncate(j);
+                    }
+                }
+                res.push(Token::make_keyword("JOIN"));
+            }
+            _ => res.push(tok),
+        }
+    }
+
+    res
+}
+
+/// `:` is part of identifiers for urls like `file:///tmp/a.csv`, so a cast like
+/// `pid::int` is tokenized as a single word, which is split again here
+fn split_casts(tokens: Vec<tokenizer::Token>) -> Vec<tokenizer::Token> {
+    use tokenizer::{Token, Word};
+
+    let mut res = Vec::with_capacity(tokens.len());
+    for tok in tokens {
+        match tok {
+            Token::Word(Word { ref value, quote_style: None, .. }) if value.contains("::") => {
+                for (i, part) in value.split("::").enumerate() {
+                    if i > 0 {
+                        res.push(Token::DoubleColon);
+                    }
+                    if !part.is_empty() {
+                        res.push(Token::make_word(part, None));
+                    }
+                }
+            }
+            tok => res.push(tok),
+        }
+    }
+    res
+}
+
+const DISTINCT_ON_MARKER: &str = "__sq_distinct_on";
+
+/// sqlparser has no syntax for `DISTINCT ON (keys)`, which is rewritten into
+/// `DISTINCT <marker>(keys),` so the keys are parsed as the first select item
+fn rewrite_distinct_on(tokens: Vec<tokenizer::Token>) -> Vec<tokenizer::Token> {
+    use tokenizer::{Token, Word};
+    use sqlparser::keywords::Keyword;
+
+    let mut res: Vec<Token> = Vec::with_capacity(tokens.len());
+    // nesting level of parentheses while in the keys
+    let mut depth = None;
+    for tok in tokens {
+        if let Some(d) = depth.as_mut() {
+            match tok {
+                Token::LParen => *d += 1,
+                Token::RParen => *d -= 1,
+                _ => {}
+            }
+            let end = *d == 0 && tok == Token::RParen;
+            res.push(tok);
+            if end {
+                res.push(Token::Comma);
+                depth = None;
+            }
+            continue;
+        }
+
+        let prev = res.iter().rev().find(|t| !matches!(t, Token::Whitespace(_)));
+        match (prev, &tok) {
+            (
+                Some(Token::Word(Word { keyword: Keyword::DISTINCT, .. })),
+                Token::Word(Word { keyword: Keyword::ON, .. }),
+            ) => {
+                res.push(Token::make_word(DISTINCT_ON_MARKER, None));
+                depth = Some(0);
+            }
+            _ => res.push(tok),
+        }
+    }
+    res
+}
+
+const WILDCARD_EXCEPT_MARKER: &str = "__sq_wildcard_except";
+const WILDCARD_REPLACE_MARKER: &str = "__sq_wildcard_replace";
+
+/// sqlparser has no syntax for `* EXCEPT (cols)` and `* REPLACE (expr AS col)`,
+/// which are rewritten into `*, <marker>(cols)` and `*, <marker>(expr, 'col')`,
+/// so the options are parsed as the select item following the wildcard
+fn rewrite_wildcard_options(tokens: Vec<tokenizer::Token>) -> Vec<tokenizer::Token> {
+    use tokenizer::{Token, Word};
+    use sqlparser::keywords::Keyword;
+
+    let mut res: Vec<Token> = Vec::with_capacity(tokens.len());
+    // nesting level of parentheses while in the options, and whether they are
+    // the replacements
+    let mut options: Option<(usize, bool)> = None;
+    let mut alias = false;
+    // whether the last token closed the options, which may be followed by more
+    let mut closed = false;
+    for (i, tok) in tokens.iter().cloned().enumerate() {
+        if let Some((depth, replacing)) = options.as_mut() {
+            match &tok {
+                Token::LParen => *depth += 1,
+                Token::RParen => *depth -= 1,
+                _ => {}
+            }
+            let (depth, replacing) = (*depth, *replacing);
+            if depth == 0 && tok == Token::RParen {
+                options = None;
+                closed = true;
+            }
+            match tok {
+                Token::Word(Word { keyword: Keyword::AS, .. }) if replacing && depth == 1 => {
+                    res.push(Token::Comma);
+                    alias = true;
+                }
+                Token::Word(Word { value, .. }) if alias => {
+                    res.push(Token::SingleQuotedString(value));
+                    alias = false;
+                }
+                tok => res.push(tok),
+            }
+            continue;
+        }
+
+        // a wildcard rather than a multiplication follows SELECT, a comma or a
+        // qualifier, options may also follow other options
+        let mut before = res.iter().rev().filter(|t| !matches!(t, Token::Whitespace(_)));
+        let wildcard = closed
+            || matches!(before.next(), Some(Token::Mul))
+            && matches!(
+                before.next(),
+                None | Some(Token::Comma | Token::Period)
+                    | Some(Token::Word(Word { keyword: Keyword::SELECT | Keyword::DISTINCT | Keyword::ALL, .. }))
+            );
+        let marker = match &tok {
+            Token::Word(Word { value, quote_style: None, .. }) if wildcard => match value.to_uppercase().as_str() {
+                "EXCEPT" | "EXCLUDE" => Some(WILDCARD_EXCEPT_MARKER),
+                "REPLACE" => Some(WILDCARD_REPLACE_MARKER),
+                _ => None,
+            },
+            _ => None,
+        };
+        let next = tokens[i + 1..].iter().find(|t| !matches!(t, Token::Whitespace(_)));
+        match marker {
+            _ if matches!(tok, Token::Whitespace(_)) => res.push(tok),
+            Some(marker) if next == Some(&Token::LParen) => {
+                closed = false;
+                res.push(Token::Comma);
+                res.push(Token::make_word(marker, None));
+                options = Some((0, marker == WILDCARD_REPLACE_MARKER));
+            }
+            _ => {
+                closed = false;
+                res.push(tok);
+            }
+        }
+    }
+    res
+}
+
+/// MySQL's `a [NOT] REGEXP b` and `a [NOT] RLIKE b` are rewritten into `a ~ b`
+/// and `a !~ b`, which sqlparser understands. only a word between two operands
+/// is rewritten, elsewhere it's a column or an alias
+fn rewrite_regexp(tokens: Vec<tokenizer::Token>) -> Vec<tokenizer::Token> {
+    use sqlparser::keywords::Keyword;
+    use tokenizer::{Token, Word};
+
+    // keywords that can't end the left operand nor start the right one
+    const NOT_OPERAND: &[Keyword] = &[
+        Keyword::SELECT, Keyword::DISTINCT, Keyword::FROM, Keyword::WHERE, Keyword::AND, Keyword::OR, Keyword::NOT,
+        Keyword::ON, Keyword::USING, Keyword::JOIN, Keyword::AS, Keyword::BY, Keyword::GROUP, Keyword::ORDER,
+        Keyword::HAVING, Keyword::LIMIT, Keyword::OFFSET, Keyword::WHEN, Keyword::THEN, Keyword::ELSE, Keyword::IS,
+        Keyword::IN, Keyword::LIKE, Keyword::ILIKE, Keyword::BETWEEN, Keyword::UNION, Keyword::EXCEPT,
+        Keyword::INTERSECT,
+    ];
+    let operand = |tok: Option<&Token>, start: bool| match tok {
+        Some(Token::Word(w)) => w.quote_style.is_some() || !NOT_OPERAND.contains(&w.keyword),
+        Some(Token::Number(..) | Token::SingleQuotedString(_) | Token::NationalStringLiteral(_)
+            | Token::EscapedStringLiteral(_) | Token::HexStringLiteral(_) | Token::Placeholder(_)) => true,
+        Some(Token::RParen) => !start,
+        Some(Token::LParen | Token::Minus | Token::Plus) => start,
+        _ => false,
+    };
+    let solid = |t: &&Token| !matches!(t, Token::Whitespace(_));
+
+    let mut res: Vec<Token> = Vec::with_capacity(tokens.len());
+    for (i, tok) in tokens.iter().enumerate() {
+        match tok {
+            Token::Word(Word { value, quote_style: None, .. })
+                if value.eq_ignore_ascii_case("regexp") || value.eq_ignore_ascii_case("rlike") =>
+            {
+                let prev = res.iter().rposition(|t| !matches!(t, Token::Whitespace(_)));
+                let negated = prev.filter(|&p| matches!(&res[p], Token::Word(Word { keyword: Keyword::NOT, .. })));
+                let left = match negated {
+                    Some(p) => res[..p].iter().rev().find(solid),
+                    None => prev.map(|p| &res[p]),
+                };
+                let right = tokens[i + 1..].iter().find(solid);
+                match negated {
+                    _ if !operand(left, false) || !operand(right, true) => res.push(tok.clone()),
+                    Some(p) => res[p] = Token::ExclamationMarkTilde,
+                    None => res.push(Token::Tilde),
+                }
+            }
+            _ => res.push(tok.clone()),
+        }
+    }
+    res
+}
+
+/// bare `?` placeholders are numbered by their position like `?1`, and a
+/// placeholder in place of a table becomes the table name, so the url of a
+/// source can be a parameter too
+fn rewrite_placeholders(tokens: Vec<tokenizer::Token>) -> Vec<tokenizer::Token> {
+    use sqlparser::keywords::Keyword;
+    use tokenizer::{Token, Word};
+
+    let mut res: Vec<Token> = Vec::with_capacity(tokens.len());
+    let mut n = 0;
+    let mut iter = tokens.into_iter().peekable();
+    while let Some(tok) = iter.next() {
+        let placeholder = match tok {
+            Token::Placeholder(p) if p == "?" => {
+                n += 1;
+                format!("?{}", n)
+            }
+            Token::Placeholder(p) => p,
+            tok @ Token::Colon if matches!(iter.peek(), Some(Token::Word(_))) => match iter.next() {
+                Some(Token::Word(w)) => format!("{}{}", tok, w.value),
+                _ => unreachable!(),
+            },
+            tok => {
+                res.push(tok);
+                continue;
+            }
+        };
+        let prev = res.iter().rev().find(|t| !matches!(t, Token::Whitespace(_)));
+        if matches!(prev, Some(Token::Word(Word { keyword: Keyword::FROM | Keyword::JOIN, .. }))) {
+            res.push(Token::make_word(&placeholder, None));
         } else {
-            todo!("not implemented")
+            res.push(Token::Placeholder(placeholder));
         }
     }
+    res
 }
 
+/// tokenize `sql` and rewrite the syntax sqlparser doesn't know
+fn tokenize(dialect: &MyDialect, sql: &str) -> Result<Vec<tokenizer::Token>, SqError> {
+    let tokens = tokenizer::Tokenizer::new(dialect, sql).tokenize().map_err(parser::ParserError::from)?;
+    let tokens = rewrite_wildcard_options(rewrite_regexp(rewrite_distinct_on(rewrite_semi_anti_joins(split_casts(tokens)))));
+    Ok(rewrite_placeholders(tokens))
+}
 
 pub fn parse<S: AsRef<str>>(sql: S) -> Result<Query, SqError> {
     let dialect = MyDialect::new();
-    match parser::Parser::parse_sql(&dialect, sql.as_ref())?[0] {
+    let tokens = tokenize(&dialect, sql.as_ref())?;
+    let mut params = vec![];
+    for tok in tokens.iter() {
+        let p = match tok {
+            tokenizer::Token::Placeholder(p) => p,
+            tokenizer::Token::Word(w) if w.quote_style.is_none() && w.value.starts_with(['?', '$', ':']) => &w.value,
+            _ => continue,
+        };
+        if !params.contains(p) {
+            params.push(p.clone());
+        }
+    }
+    let mut parser = parser::Parser::new(tokens, &dialect);
+    let statement = parser.parse_statement()?;
+    // scripts are run by a session, anything after the statement is an error
+    // rather than ignored
+    let expected = if parser.consume_token(&tokenizer::Token::SemiColon) { "a single statement" } else { "end of statement" };
+    if parser.peek_token() != tokenizer::Token::EOF {
+        return Err(parser.expected::<()>(expected, parser.peek_token()).unwrap_err().into());
+    }
+
+    match statement {
         ast::Statement::Query(ref query) => {
-            SqlSelect(query).try_into()
+            let query = Query::try_from(SqlSelect(query)).map_err(|e| e.locate(sql.as_ref()))?;
+            Ok(Query { params, ..query })
         }
-        _ => Err(sqlparser::parser::ParserError::ParserError("sql not supported".to_owned()).into()),
+        statement => Err(SqError::unsupported(statement).locate(sql.as_ref())),
     }
 }
 
+/// sqlparser can't parse `COPY (query) TO` nor options other than PostgreSQL's,
+/// so COPY is parsed here after its keyword
+fn parse_copy(parser: &mut parser::Parser) -> Result<Statement, SqError> {
+    use sqlparser::keywords::Keyword;
+    use tokenizer::Token;
+
+    let query = if parser.consume_token(&Token::LParen) {
+        let query = parser.parse_query()?;
+        parser.expect_token(&Token::RParen)?;
+        SqlSelect(&query).try_into()?
+    } else {
+        let name = object_name(&parser.parse_object_name()?);
+        Query::select_all(Relation::Table { name, alias: None })
+    };
+    parser.expect_keyword(Keyword::TO)?;
+    let target = match parser.peek_token() {
+        Token::SingleQuotedString(_) => parser.parse_literal_string()?,
+        _ => object_name(&parser.parse_object_name()?),
+    };
+
+    let _ = parser.parse_keyword(Keyword::WITH);
+    let mut options = vec![];
+    if parser.consume_token(&Token::LParen) {
+        loop {
+            let name = parser.parse_identifier()?.value.to_lowercase();
+            let value = match parser.next_token() {
+                Token::Word(w) => w.value,
+                Token::SingleQuotedString(s) | Token::Number(s, _) => s,
+                tok => return Err(parser.expected::<()>("option value", tok).unwrap_err().into()),
+            };
+            options.push((name, value));
+            if !parser.consume_token(&Token::Comma) {
+                break;
+            }
+        }
+        parser.expect_token(&Token::RParen)?;
+    }
+    Ok(Statement::Copy { query, target, options })
+}
+
+/// parse a script of statements separated by `;`
+pub fn parse_script<S: AsRef<str>>(sql: S) -> Result<Vec<Statement>, SqError> {
+    let dialect = MyDialect::new();
+    let tokens = tokenize(&dialect, sql.as_ref())?;
+    let mut parser = parser::Parser::new(tokens, &dialect);
+    let mut statements = vec![];
+    let mut separated = true;
+    loop {
+        while parser.consume_token(&tokenizer::Token::SemiColon) {
+            separated = true;
+        }
+        if parser.peek_token() == tokenizer::Token::EOF {
+            break;
+        }
+        if !separated {
+            return Err(parser.expected::<()>("end of statement", parser.peek_token()).unwrap_err().into());
+        }
+        if parser.parse_keyword(sqlparser::keywords::Keyword::COPY) {
+            statements.push(parse_copy(&mut parser).map_err(|e| e.locate(sql.as_ref()))?);
+        } else {
+            let statement = parser.parse_statement()?;
+            statements.push(Statement::try_from(SqlStatement(&statement)).map_err(|e| e.locate(sql.as_ref()))?);
+        }
+        separated = false;
+    }
+    Ok(statements)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -271,6 +1710,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn test_parse_2() {
         let url = "https://raw.githubusercontent.com/owid/covid-19-data/master/public/data/latest/owid-covid-latest.csv";
         let sql = format!(r#"
@@ -284,7 +1724,7 @@ mod tests {
         assert!(res.is_ok());
 
         let q = res.unwrap();
-        assert_eq!(q.source, Some(url.to_owned())); 
+        assert_eq!(q.source, Some(Relation::Table { name: url.to_owned(), alias: None }));
         assert_eq!(q.limit, Some(10));
         match q.condition {
             Some(dsl::Expr::BinaryExpr { left, op: dsl::Operator::And, right }) => {
@@ -306,4 +1746,444 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_group_by() {
+        let sql = r#"
+            select continent, count(*), sum(total_cases) as cases
+            from file:///tmp/covid.csv
+            group by continent
+            "#;
+        let q = parse(sql).unwrap();
+        assert!(q.is_aggregated());
+        assert_eq!(q.group_by, vec![col("continent")]);
+        assert_eq!(q.projections[0], col("continent"));
+        assert_eq!(q.projections[1], dsl::count().alias("count(*)"));
+        assert_eq!(q.projections[2], col("total_cases").sum().alias("cases"));
+
+        let sql = "select location, sum(total_cases) from file:///tmp/covid.csv group by continent";
+        assert!(matches!(parse(sql), Err(SqError::AstError(_))));
+    }
+
+    #[test]
+    fn test_parse_joins() {
+        let sql = r#"
+            select a.location, b.population
+            from file:///tmp/covid.csv a
+            left join file:///tmp/population.parquet as b on a.iso_code = b.code
+            left semi join cmd://ps?au p using (pid)
+            "#;
+        let q = parse(sql).unwrap();
+        assert_eq!(q.source, Some(Relation::Table { name: "file:///tmp/covid.csv".to_owned(), alias: Some("a".to_owned()) }));
+        assert_eq!(q.projections, vec![col("a.location"), col("b.population")]);
+        assert_eq!(q.joins.len(), 2);
+        assert_eq!(q.joins[0], Join {
+            relation: Relation::Table { name: "file:///tmp/population.parquet".to_owned(), alias: Some("b".to_owned()) },
+            kind: JoinKind::Left,
+            constraint: JoinConstraint::On(col("a.iso_code").eq(col("b.code"))),
+        });
+        assert_eq!(q.joins[1], Join {
+            relation: Relation::Table { name: "cmd://ps?au".to_owned(), alias: Some("p".to_owned()) },
+            kind: JoinKind::Semi,
+            constraint: JoinConstraint::Using(vec!["pid".to_owned()]),
+        });
+        assert_eq!(q.sources(), ["file:///tmp/covid.csv", "file:///tmp/population.parquet", "cmd://ps?au"]);
+
+        let sql = "select * from file:///tmp/a.csv a join file:///tmp/b.csv b";
+        assert!(matches!(parse(sql), Err(SqError::AstError(_))));
+
+        // right after a relation, semi and anti are aliases
+        let sql = "select * from file:///tmp/a.csv semi join file:///tmp/b.csv anti on semi.k = anti.k anti join cmd://ps p using (k)";
+        let q = parse(sql).unwrap();
+        assert_eq!(q.source, Some(Relation::Table { name: "file:///tmp/a.csv".to_owned(), alias: Some("semi".to_owned()) }));
+        assert_eq!(q.joins[0].relation, Relation::Table { name: "file:///tmp/b.csv".to_owned(), alias: Some("anti".to_owned()) });
+        assert_eq!(q.joins[0].kind, JoinKind::Inner);
+        assert_eq!(q.joins[1].kind, JoinKind::Anti);
+    }
+
+    #[test]
+    fn test_parse_subqueries() {
+        let sql = r#"
+            select t.location from (select location, new_cases from file:///tmp/covid.csv) as t(location, cases)
+            where cases > (select avg(new_cases) from file:///tmp/covid.csv)
+              and location in (select name from file:///tmp/population.csv)
+            "#;
+        let q = parse(sql).unwrap();
+        match q.source {
+            Some(Relation::Derived { query, alias, columns }) => {
+                assert_eq!(query.sources(), ["file:///tmp/covid.csv"]);
+                assert_eq!(alias.as_deref(), Some("t"));
+                assert_eq!(columns, ["location", "cases"]);
+            }
+            _ => panic!("source should be a derived table"),
+        }
+        assert_eq!(q.condition, Some(col("cases").gt(col(&subquery_placeholder(0))).and(col(&subquery_placeholder(1)))));
+        assert_eq!(q.subqueries.len(), 2);
+        assert_eq!(q.subqueries[0].kind, SubqueryKind::Scalar);
+        assert_eq!(q.subqueries[1].kind, SubqueryKind::In { expr: col("location"), negated: false });
+        assert_eq!(q.subqueries[1].query.sources(), ["file:///tmp/population.csv"]);
+    }
+
+    #[test]
+    fn test_parse_ctes() {
+        let sql = r#"
+            with a as (select * from file:///tmp/a.csv), b(id) as (select id from a)
+            select * from a join b on a.id = b.id join file:///tmp/c.csv c on c.id = a.id
+            "#;
+        let q = parse(sql).unwrap();
+        assert_eq!(q.ctes.len(), 2);
+        assert_eq!(q.ctes[1].name, "b");
+        assert_eq!(q.ctes[1].columns, ["id"]);
+        assert_eq!(q.sources(), ["file:///tmp/a.csv", "file:///tmp/c.csv"]);
+    }
+
+    #[test]
+    fn test_parse_set_operations() {
+        let sql = r#"
+            select location from file:///tmp/a.csv
+            union all (select name from file:///tmp/b.csv except select name from file:///tmp/c.csv)
+            order by location limit 3
+            "#;
+        let q = parse(sql).unwrap();
+        assert_eq!(q.projections, vec![dsl::Expr::Wildcard]);
+        assert_eq!(q.order_by, vec![OrderBy { expr: col("location"), desc: false, nulls_first: None }]);
+        assert_eq!(q.limit, Some(3));
+        match &q.source {
+            Some(Relation::SetOperation { op: SetOperator::Union, all: true, left, right }) => {
+                assert_eq!(left.projections, vec![col("location")]);
+                assert!(matches!(right.source, Some(Relation::SetOperation { op: SetOperator::Except, all: false, .. })));
+            }
+            _ => panic!("source should be a set operation"),
+        }
+        assert_eq!(q.sources(), ["file:///tmp/a.csv", "file:///tmp/b.csv", "file:///tmp/c.csv"]);
+    }
+
+    #[test]
+    fn test_parse_scalar_functions() {
+        let sql = r#"
+            select upper(location), trim(leading 'x' from name) as name
+            from file:///tmp/covid.csv
+            where coalesce(new_cases, total_cases) > 100
+            "#;
+        let q = parse(sql).unwrap();
+        assert_eq!(q.projections[0], col("location").str().to_uppercase().alias("upper(location)"));
+        assert_eq!(q.projections[1], col("name").str().lstrip(Some('x')).alias("name"));
+        assert_eq!(q.condition, Some(coalesce(&[col("new_cases"), col("total_cases")]).gt(lit(100i64))));
+
+        let sql = "select no_such_function(location) from file:///tmp/covid.csv";
+        assert!(matches!(parse(sql), Err(SqError::Unsupported { .. })));
+        let sql = "select upper(location, name) from file:///tmp/covid.csv";
+        assert!(matches!(parse(sql), Err(SqError::AstError(_))));
+    }
+
+    #[test]
+    fn test_parse_cast_and_case() {
+        let sql = r#"
+            select cast(pid as int), pid::bigint as id, try_cast(cpu as float) as cpu,
+                   case when cpu > 50 then 'busy' else 'idle' end as load
+            from cmd://ps?aux
+            "#;
+        let q = parse(sql).unwrap();
+        assert_eq!(q.projections[0], col("pid").strict_cast(DataType::Int32).alias("CAST(pid AS INT)"));
+        assert_eq!(q.projections[1], col("pid").strict_cast(DataType::Int64).alias("id"));
+        assert_eq!(q.projections[2], col("cpu").cast(DataType::Float64).alias("cpu"));
+        assert_eq!(q.projections[3], when(col("cpu").gt(lit(50i64))).then(lit("busy")).otherwise(lit("idle")).alias("load"));
+
+        let sql = "select cast(pid as bytea) from cmd://ps?aux";
+        assert!(matches!(parse(sql), Err(SqError::Unsupported { .. })));
+    }
+
+    #[test]
+    fn test_parse_predicates() {
+        let sql = r#"
+            select pid from cmd://ps?aux
+            where user in ('root', 'www') and pid not between 1 and 100 and command like '%sq\_%' escape '\'
+              and tty is not null and not (cpu > -1.5) and user || ':' || pid regexp '^r'
+            "#;
+        let q = parse(sql).unwrap();
+        let mut preds = vec![];
+        let mut e = q.condition.unwrap();
+        while let dsl::Expr::BinaryExpr { left, op: dsl::Operator::And, right } = e {
+            preds.push(*right);
+            e = *left;
+        }
+        preds.push(e);
+        preds.reverse();
+        assert_eq!(preds.len(), 6);
+        assert_eq!(preds[0], col("user").eq(lit("root")).or(col("user").eq(lit("www"))));
+        assert_eq!(preds[1], col("pid").gt_eq(lit(1i64)).and(col("pid").lt_eq(lit(100i64))).not());
+        assert_eq!(preds[2], col("command").str().contains("^(?s).*sq_.*$"));
+        assert_eq!(preds[3], col("tty").is_not_null());
+        assert_eq!(preds[4], col("cpu").gt(lit(-1.5)).not());
+        let user_pid = concat_str([concat_str([col("user").cast(DataType::Utf8), lit(":").cast(DataType::Utf8)], "")
+            .cast(DataType::Utf8), col("pid").cast(DataType::Utf8)], "");
+        assert_eq!(preds[5], user_pid.str().contains("^r"));
+
+        let sql = "select pid from cmd://ps?aux where command like user";
+        assert!(matches!(parse(sql), Err(SqError::AstError(_))));
+        let sql = "select pid from cmd://ps?aux where command ~ '('";
+        assert!(matches!(parse(sql), Err(SqError::AstError(_))));
+
+        // regexp and rlike are columns or aliases outside of operands
+        let sql = "select regexp, pid rlike from cmd://ps?aux where regexp not regexp '^r'";
+        let q = parse(sql).unwrap();
+        assert_eq!(q.projections, [col("regexp"), col("pid").alias("rlike")]);
+        assert_eq!(q.condition, Some(col("regexp").str().contains("^r").not()));
+    }
+
+    #[test]
+    fn test_parse_distinct() {
+        let q = parse("select distinct continent from file:///tmp/covid.csv").unwrap();
+        assert_eq!(q.distinct, Some(vec![]));
+        assert_eq!(q.projections, vec![col("continent")]);
+
+        let sql = r#"
+            select distinct on (continent, (new_cases > 10)) continent, location
+            from file:///tmp/covid.csv order by continent, total_cases desc
+            "#;
+        let q = parse(sql).unwrap();
+        assert_eq!(q.distinct, Some(vec![col("continent"), col("new_cases").gt(lit(10i64))]));
+        assert_eq!(q.projections, vec![col("continent"), col("location")]);
+
+        let q = parse("select location from file:///tmp/covid.csv").unwrap();
+        assert_eq!(q.distinct, None);
+    }
+
+    #[test]
+    fn test_parse_windows() {
+        let sql = r#"
+            select location, row_number() over (partition by continent order by total_cases desc) as n,
+                   sum(new_cases) over (order by location rows between 2 preceding and current row) as recent
+            from file:///tmp/covid.csv
+            "#;
+        let q = parse(sql).unwrap();
+        assert_eq!(q.projections[1], col(&window_placeholder(0)).alias("n"));
+        assert_eq!(q.windows.len(), 2);
+        assert_eq!(q.windows[0].partition_by, vec![col("continent")]);
+        assert_eq!(q.windows[0].order_by, vec![(col("total_cases"), true)]);
+        assert_eq!(q.windows[1].partition_by, vec![]);
+        assert_eq!(q.windows[1].order_by, vec![(col("location"), false)]);
+
+        let sql = "select sum(new_cases) over (rows between 1 following and 2 following) from file:///tmp/covid.csv";
+        assert!(matches!(parse(sql), Err(SqError::Unsupported { .. })));
+        let sql = "select continent, rank() over () from file:///tmp/covid.csv group by continent";
+        assert!(matches!(parse(sql), Err(SqError::AstError(_))));
+    }
+
+    #[test]
+    fn test_parse_select_items() {
+        let sql = r#"
+            select total_cases * 2 as doubled, new_cases + 1, t.*, 2 * replace(location, 'a', 'b')
+            from file:///tmp/covid.csv t
+            "#;
+        let q = parse(sql).unwrap();
+        assert_eq!(q.projections[0], (col("total_cases") * lit(2i64)).alias("doubled"));
+        assert_eq!(q.projections[1], (col("new_cases") + lit(1i64)).alias("new_cases + 1"));
+        assert_eq!(q.projections[2], col("t.*"));
+        assert_eq!(q.projections.len(), 4);
+
+        let sql = r#"
+            select * except (new_cases, total_cases) from file:///tmp/covid.csv
+            "#;
+        let q = parse(sql).unwrap();
+        assert_eq!(q.projections, vec![dsl::Expr::Exclude(
+            Box::new(dsl::Expr::Wildcard),
+            vec![dsl::Excluded::Name(Arc::from("new_cases")), dsl::Excluded::Name(Arc::from("total_cases"))],
+        )]);
+
+        let sql = r#"
+            select t.* replace (upper(location) as location, cast(new_cases as int) as new_cases)
+            from file:///tmp/covid.csv t
+            "#;
+        let q = parse(sql).unwrap();
+        assert_eq!(q.projections, vec![col("t.*")]);
+        assert_eq!(q.replace.len(), 2);
+        assert_eq!(q.replace[0], functions::scalar("upper", vec![col("location")]).unwrap().alias("location"));
+
+        let sql = "select location, replace (new_cases as n) from file:///tmp/covid.csv";
+        assert!(parse(sql).is_err());
+    }
+
+    #[test]
+    fn test_parse_order_by() {
+        let sql = r#"
+            select location, new_cases * 2 as doubled from file:///tmp/covid.csv
+            order by 2 desc nulls first, doubled nulls last, location
+            "#;
+        let q = parse(sql).unwrap();
+        assert_eq!(q.order_by, vec![
+            OrderBy { expr: dsl::Expr::Nth(1), desc: true, nulls_first: Some(true) },
+            OrderBy { expr: col("doubled"), desc: false, nulls_first: Some(false) },
+            OrderBy { expr: col("location"), desc: false, nulls_first: None },
+        ]);
+
+        let sql = "select location from file:///tmp/covid.csv order by 0";
+        assert!(matches!(parse(sql), Err(SqError::AstError(_))));
+    }
+
+    #[test]
+    fn test_parse_literals() {
+        let sql = r#"
+            select 1, 1.5, 99999999999999999999, -2, null, true, x'4142', interval '1 day 2 hours', interval '3' minute
+        "#;
+        let q = parse(sql).unwrap();
+        assert_eq!(q.projections[0], lit(1i64));
+        assert_eq!(q.projections[1], lit(1.5));
+        assert_eq!(q.projections[2], lit(1e20));
+        assert_eq!(q.projections[3], lit(-2i64).alias("-2"));
+        assert_eq!(q.projections[4], dsl::Expr::Literal(LiteralValue::Null));
+        assert_eq!(q.projections[5], lit(true));
+        assert_eq!(q.projections[6], lit("AB"));
+
+        let sql = "select date '2022-13-01' from file:///tmp/covid.csv";
+        assert!(matches!(parse(sql), Err(SqError::AstError(_))));
+        let sql = "select interval '1 month' from file:///tmp/covid.csv";
+        assert!(matches!(parse(sql), Err(SqError::Unsupported { .. })));
+        let sql = "select x'414' from file:///tmp/covid.csv";
+        assert!(matches!(parse(sql), Err(SqError::Unsupported { .. })));
+    }
+
+    #[test]
+    fn test_parse_temporal() {
+        let sql = r#"
+            select extract(year from d), date_part('dow', d), date_trunc('month', d), d at time zone '+05:30', now()
+            from file:///tmp/dates.csv where d > current_date - interval '7 days'
+            "#;
+        let q = parse(sql).unwrap();
+        assert_eq!(q.projections[0], col("d").dt().year().alias("EXTRACT(YEAR FROM d)"));
+        assert_eq!(q.projections.len(), 5);
+
+        let sql = "select date_trunc('fortnight', d) from file:///tmp/dates.csv";
+        assert!(matches!(parse(sql), Err(SqError::Unsupported { .. })));
+        let sql = "select d at time zone 'Europe/Paris' from file:///tmp/dates.csv";
+        assert!(parse(sql).is_ok());
+        let sql = "select d at time zone 'Europe/Atlantis' from file:///tmp/dates.csv";
+        assert!(matches!(parse(sql), Err(SqError::Unsupported { .. })));
+    }
+
+    #[test]
+    fn test_parse_unsupported() {
+        let sql = "select location\nfrom file:///tmp/covid.csv\nwhere location similar to 'J%'";
+        let err = parse(sql).unwrap_err();
+        assert_eq!(err.to_string(), "unsupported: location SIMILAR TO 'J%' at line 3, column 7");
+
+        let sql = "select location from file:///tmp/covid.csv fetch first 3 rows only";
+        match parse(sql) {
+            Err(SqError::Unsupported { fragment, span }) => {
+                assert_eq!(fragment, "FETCH FIRST 3 ROWS ONLY");
+                assert_eq!(span, Some(crate::Span { line: 1, column: 44 }));
+            }
+            res => panic!("{:?} should be unsupported", res),
+        }
+
+        let sql = "insert into t values (1)";
+        assert!(matches!(parse(sql), Err(SqError::Unsupported { span: Some(crate::Span { line: 1, column: 1 }), .. })));
+    }
+
+    #[test]
+    fn test_parse_params() {
+        let sql = "select ?, location from ? where total_cases > ? and continent = :continent or new_cases < $1";
+        let q = parse(sql).unwrap();
+        assert_eq!(q.projections[0], dsl::col("__sq_param_?1").alias("?1"));
+        assert_eq!(q.source, Some(Relation::Table { name: "?2".to_owned(), alias: None }));
+        assert_eq!(q.condition, Some(
+            dsl::col("total_cases").gt(dsl::col("__sq_param_?3"))
+                .and(dsl::col("continent").eq(dsl::col("__sq_param_:continent")))
+                .or(dsl::col("new_cases").lt(dsl::col("__sq_param_$1")))));
+
+        let params = Params::new().bind(1).bind("file:///tmp/covid.csv").bind(2.5).bind_named("continent", "Asia");
+        let mut bound = parse(sql).unwrap();
+        assert_eq!(bound.params, ["?1", "?2", "?3", ":continent", "$1"]);
+        bound.bind(&params).unwrap();
+        assert_eq!(bound.sources(), ["file:///tmp/covid.csv"]);
+        assert_eq!(bound.projections[0], dsl::lit(1i64).alias("?1"));
+        assert_eq!(bound.condition, Some(
+            dsl::col("total_cases").gt(dsl::lit(2.5))
+                .and(dsl::col("continent").eq(dsl::lit("Asia")))
+                .or(dsl::col("new_cases").lt(dsl::lit(1i64)))));
+
+        let mut q = parse("select * from file:///tmp/covid.csv where location = :name").unwrap();
+        assert_eq!(q.bind(&params).unwrap_err().to_string(), "ast: no value bound to parameter :name");
+        let mut q = parse("select * from $2").unwrap();
+        assert!(q.bind(&Params::new().bind(1).bind(2)).is_err());
+    }
+
+    #[test]
+    fn test_parse_script() {
+        let sql = "
+            set infer_schema_length = 100;
+            create view asia (country, cases) as select location, total_cases from file:///tmp/covid.csv where continent = 'Asia';
+            create table top as select * from asia order by cases desc limit 1;
+            select * from top;
+            ";
+        let statements = parse_script(sql).unwrap();
+        assert_eq!(statements.len(), 4);
+        assert_eq!(statements[0], Statement::Set { name: "infer_schema_length".to_owned(), value: "100".to_owned() });
+        match &statements[1] {
+            Statement::CreateView { view, or_replace: false } => {
+                assert_eq!(view.name, "asia");
+                assert_eq!(view.columns, ["country", "cases"]);
+                assert_eq!(view.query.sources(), ["file:///tmp/covid.csv"]);
+            }
+            s => panic!("{:?} should be a view", s),
+        }
+        assert!(matches!(&statements[2], Statement::CreateTable { table: Cte { name, .. }, .. } if name == "top"));
+        assert!(matches!(&statements[3], Statement::Query(q) if q.sources() == ["top"]));
+
+        let sql = "create or replace temp view v as select 1; create table if not exists t as select 1; drop view if exists v, w";
+        let statements = parse_script(sql).unwrap();
+        assert!(matches!(&statements[0], Statement::CreateView { view, or_replace: true } if view.name == "v"));
+        assert!(matches!(&statements[1], Statement::CreateTable { or_replace: false, if_not_exists: true, .. }));
+        assert_eq!(statements[2], Statement::Drop { view: true, names: vec!["v".to_owned(), "w".to_owned()], if_exists: true });
+        let sql = "create materialized view m as select 1";
+        assert!(matches!(&parse_script(sql).unwrap()[0], Statement::CreateTable { table, .. } if table.name == "m"));
+
+        let sql = "select 1;\ndrop schema s";
+        assert!(matches!(parse_script(sql), Err(SqError::Unsupported { span: Some(crate::Span { line: 2, .. }), .. })));
+
+        // a single query is parsed whole
+        assert!(parse("select 1 from file:///tmp/covid.csv;").is_ok());
+        assert!(parse("select 1 from file:///tmp/covid.csv; drop table x").is_err());
+    }
+
+    #[test]
+    fn test_parse_copy() {
+        let sql = "copy (select location from file:///tmp/covid.csv) to 'file:///tmp/out.parquet' (format parquet, compression zstd);
+                   copy covid to file:///tmp/out.csv with (header false, delimiter ';');
+                   insert into file:///tmp/out.csv select * from covid";
+        let statements = parse_script(sql).unwrap();
+        match &statements[0] {
+            Statement::Copy { query, target, options } => {
+                assert_eq!(query.sources(), ["file:///tmp/covid.csv"]);
+                assert_eq!(target, "file:///tmp/out.parquet");
+                assert_eq!(options, &[("format".to_owned(), "parquet".to_owned()), ("compression".to_owned(), "zstd".to_owned())]);
+            }
+            s => panic!("{:?} should be a copy", s),
+        }
+        match &statements[1] {
+            Statement::Copy { query, target, options } => {
+                assert_eq!(query, &Query::select_all(Relation::Table { name: "covid".to_owned(), alias: None }));
+                assert_eq!(target, "file:///tmp/out.csv");
+                assert_eq!(options[1], ("delimiter".to_owned(), ";".to_owned()));
+            }
+            s => panic!("{:?} should be a copy", s),
+        }
+        assert!(matches!(&statements[2], Statement::Insert { target, query } if target == "file:///tmp/out.csv" && query.sources() == ["covid"]));
+
+        assert!(parse_script("copy (select 1) to").is_err());
+        assert!(matches!(parse_script("insert into t (a) select 1"), Err(SqError::Unsupported { .. })));
+    }
+
+    #[test]
+    fn test_parse_explain() {
+        let statements = parse_script("explain analyze select location from file:///tmp/covid.csv limit 3").unwrap();
+        match &statements[0] {
+            Statement::Explain { query, analyze: true } => {
+                let fields = query.describe().into_iter().map(|(name, _)| name).collect::<Vec<_>>();
+                assert_eq!(fields, ["projections", "source", "limit"]);
+            }
+            s => panic!("{:?} should be an explain", s),
+        }
+        assert!(matches!(parse_script("explain select 1").unwrap()[0], Statement::Explain { analyze: false, .. }));
+        assert!(matches!(parse_script("explain drop table t"), Err(SqError::Unsupported { .. })));
+    }
 }
@@ -0,0 +1,915 @@
+//! logical planning of a parsed query into a polars lazy frame, clauses are
+//! applied in the SQL evaluation order: FROM, WHERE, GROUP BY, HAVING, window
+//! functions, ORDER BY, SELECT, DISTINCT and then LIMIT
+
+use std::collections::HashMap;
+use polars::prelude::*;
+
+use super::parser::*;
+use super::{functions, Diagnostic, SqError};
+
+/// aggregate `lf` by `keys`, filter groups by `having`, sort them by `order_by`,
+/// and reorder output columns as listed in `projections`
+fn aggregate(
+    lf: LazyFrame,
+    keys: Vec<Expr>,
+    projections: Vec<Expr>,
+    having: Option<Expr>,
+    order_by: Vec<(Expr, bool)>,
+) -> LazyFrame {
+    let mut aggs = vec![];
+    let mut outputs = vec![];
+    for e in projections {
+        match keys.iter().find(|k| *k == unaliased(&e)) {
+            Some(k) => {
+                let nm = output_name(k).unwrap_or_default();
+                outputs.push(match output_name(&e) {
+                    Some(alias) if alias != nm => col(&nm).alias(&alias),
+                    _ => col(&nm),
+                });
+            }
+            None => {
+                let e = match output_name(&e) {
+                    Some(_) => e,
+                    None => {
+                        let nm = e.to_string();
+                        e.alias(&nm)
+                    }
+                };
+                outputs.push(col(&output_name(&e).unwrap_or_default()));
+                aggs.push(e);
+            }
+        }
+    }
+
+    // aggregates in HAVING refer to the projected ones if possible, otherwise they
+    // are computed as hidden columns which are dropped by the final projection
+    let having = having.map(|mut having| {
+        having.mutate().apply(|e| {
+            if matches!(e, Expr::Agg(_) | Expr::Count) {
+                let nm = match aggs.iter().find(|p| unaliased(p) == e) {
+                    Some(p) => output_name(p).unwrap_or_default().to_string(),
+                    None => {
+                        let nm = format!("__having_{}", aggs.len());
+                        aggs.push(e.clone().alias(&nm));
+                        nm
+                    }
+                };
+                *e = col(&nm);
+            }
+            true
+        });
+        having
+    });
+
+    // ORDER BY may refer to group keys and aggregates which are not projected,
+    // they are kept as hidden columns until sorted
+    let mut hidden: Vec<String> = vec![];
+    let mut by = vec![];
+    let mut desc = vec![];
+    for (mut e, d) in order_by {
+        e.mutate().apply(|e| {
+            if let Some(k) = keys.iter().find(|k| *k == e) {
+                let nm = output_name(k).unwrap_or_default().to_string();
+                if !hidden.contains(&nm) {
+                    hidden.push(nm.clone());
+                }
+                *e = col(&nm);
+            } else if matches!(e, Expr::Agg(_) | Expr::Count) {
+                let nm = format!("__sq_order_{}", aggs.len());
+                aggs.push(e.clone().alias(&nm));
+                hidden.push(nm.clone());
+                *e = col(&nm);
+            }
+            true
+        });
+        by.push(e);
+        desc.push(d);
+    }
+
+    let lf = if keys.is_empty() {
+        lf.select(aggs)
+    } else {
+        lf.groupby_stable(keys).agg(aggs)
+    };
+    let lf = match having {
+        Some(having) => lf.filter(having),
+        None => lf,
+    };
+    let lf = lf.select(outputs.into_iter().chain(hidden.iter().map(|nm| col(nm))).collect::<Vec<_>>());
+    let lf = if by.is_empty() { lf } else { lf.sort_by_exprs(by, desc, false) };
+    if hidden.is_empty() { lf } else { lf.drop_columns(hidden) }
+}
+
+/// a column visible to the query, stored in the scanned frame as `qualifier.name`
+#[derive(Debug, Clone)]
+struct ScopeColumn {
+    qualifier: String,
+    name: String,
+    /// right hand column of a `USING` join, only reachable by its qualified name
+    hidden: bool,
+}
+
+impl ScopeColumn {
+    fn qualified(&self) -> String {
+        format!("{}.{}", self.qualifier, self.name)
+    }
+}
+
+/// columns of all the relations in the FROM clause
+#[derive(Debug, Clone, Default)]
+struct Scope(Vec<ScopeColumn>);
+
+impl Scope {
+    fn contains(&self, qualified: &str) -> bool {
+        self.0.iter().any(|c| c.qualified() == qualified)
+    }
+
+    /// resolve a column reference to its qualified name, `None` if it's not a
+    /// column of any relation (e.g. a reference to an alias of projection)
+    fn resolve(&self, name: &str) -> Result<Option<String>, SqError> {
+        if self.contains(name) {
+            return Ok(Some(name.to_owned()));
+        }
+
+        let mut found = self.0.iter().filter(|c| !c.hidden && c.name == name);
+        match (found.next(), found.next()) {
+            (Some(c), None) => Ok(Some(c.qualified())),
+            (Some(_), Some(_)) => Err(SqError::AstError(format!("column reference {} is ambiguous", name))),
+            _ => Ok(None),
+        }
+    }
+
+    fn resolve_expr(&self, mut e: Expr) -> Result<Expr, SqError> {
+        let mut res = Ok(());
+        e.mutate().apply(|e| {
+            if let Expr::Column(name) = e {
+                match self.resolve(name) {
+                    Ok(Some(qualified)) => *e = col(&qualified),
+                    Ok(None) => {}
+                    Err(err) => {
+                        res = Err(err);
+                        return false;
+                    }
+                }
+            }
+            true
+        });
+        res.map(|_| e)
+    }
+
+    /// resolve a select item while keeping its output name, wildcard is expanded
+    /// to the visible columns, less the excluded ones and with the columns named
+    /// by `replace` taking their replacements
+    fn resolve_projection(&self, e: Expr, replace: &[Expr]) -> Result<Vec<Expr>, SqError> {
+        let (wildcard, excluded) = match &e {
+            Expr::Exclude(inner, excluded) => (inner.as_ref(), excluded.as_slice()),
+            e => (e, &[][..]),
+        };
+        let qualifier = match wildcard {
+            Expr::Wildcard => Some(None),
+            Expr::Column(name) => name.strip_suffix(".*").map(Some),
+            _ => None,
+        };
+        if let Some(qualifier) = qualifier {
+            if qualifier.is_some_and(|q| !self.0.iter().any(|c| c.qualifier == q)) {
+                return Err(SqError::AstError(format!("relation {} is not in FROM", qualifier.unwrap_or_default())));
+            }
+            let visible = self
+                .0
+                .iter()
+                .filter(|c| !c.hidden && qualifier.is_none_or(|q| c.qualifier == q))
+                .filter(|c| !excluded.iter().any(|x| matches!(x, Excluded::Name(nm) if nm.as_ref() == c.name)))
+                .collect::<Vec<_>>();
+            return visible
+                .iter()
+                .map(|c| {
+                    if let Some(r) = replace.iter().find(|r| output_name(r).as_deref() == Some(c.name.as_str())) {
+                        return self.resolve_expr(r.clone());
+                    }
+                    let qualified = c.qualified();
+                    Ok(if visible.iter().filter(|o| o.name == c.name).count() > 1 {
+                        col(&qualified)
+                    } else {
+                        col(&qualified).alias(&c.name)
+                    })
+                })
+                .collect();
+        }
+
+        // a qualified column is named by its bare name
+        let nm = match &e {
+            Expr::Column(name) => match self.resolve(name)? {
+                Some(qualified) => self.0.iter().find(|c| c.qualified() == qualified).map(|c| Arc::from(c.name.as_str())),
+                None => output_name(&e),
+            },
+            _ => output_name(&e),
+        };
+        let e = self.resolve_expr(e)?;
+        Ok(vec![match nm {
+            Some(nm) if output_name(&e).as_ref() != Some(&nm) => e.alias(&nm),
+            _ => e,
+        }])
+    }
+
+    /// check a resolved expression only refers to columns of the scope or output
+    /// columns of `projections`, suggesting the closest names otherwise
+    fn check(&self, e: &Expr, projections: &[Expr]) -> Result<(), SqError> {
+        let aliases = projections.iter().filter_map(output_name).collect::<Vec<_>>();
+        let unknown = e.into_iter().find_map(|e| match e {
+            Expr::Column(name)
+                if !name.starts_with("__sq_") && !self.contains(name) && !aliases.contains(name) =>
+            {
+                Some(name.clone())
+            }
+            _ => None,
+        });
+        match unknown {
+            Some(name) => {
+                let candidates = self
+                    .0
+                    .iter()
+                    .filter(|c| !c.hidden)
+                    .map(|c| c.name.clone())
+                    .chain(aliases.iter().map(|a| a.to_string()));
+                Err(SqError::Diagnostic(Box::new(Diagnostic::column_not_found(&name, candidates))))
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// tables loaded and CTEs visible while planning a query
+#[derive(Clone)]
+pub(crate) struct Context<'a> {
+    pub(crate) tables: &'a HashMap<String, DataFrame>,
+    pub(crate) ctes: HashMap<String, LazyFrame>,
+}
+
+/// scan a relation with columns renamed to their qualified names
+fn scan(relation: &Relation, ctx: &Context) -> Result<(LazyFrame, Scope), SqError> {
+    let (lf, qualifier, names) = match relation {
+        Relation::Table { name, alias } => {
+            let lf = match ctx.ctes.get(name) {
+                Some(lf) => lf.clone(),
+                None => ctx
+                    .tables
+                    .get(name)
+                    .ok_or_else(|| SqError::LoadError(format!("{} is not loaded", name)))?
+                    .clone()
+                    .lazy(),
+            };
+            let names = lf.schema()?.iter_names().map(|c| c.to_string()).collect::<Vec<_>>();
+            (lf, alias.as_ref().unwrap_or(name).clone(), names)
+        }
+        Relation::Derived { query, alias, columns } => {
+            let lf = plan(query, ctx)?;
+            let names = lf.schema()?.iter_names().map(|c| c.to_string()).collect::<Vec<_>>();
+            if columns.len() > names.len() {
+                return Err(SqError::AstError(format!(
+                    "{} has {} columns available but {} columns specified",
+                    alias.as_deref().unwrap_or_default(), names.len(), columns.len())));
+            }
+            (lf, alias.clone().unwrap_or_default(), names)
+        }
+        Relation::SetOperation { op, all, left, right } => {
+            let lf = set_operation(*op, *all, left, right, ctx)?;
+            let names = lf.schema()?.iter_names().map(|c| c.to_string()).collect::<Vec<_>>();
+            (lf, String::new(), names)
+        }
+    };
+
+    // columns of a derived table may be renamed by its alias
+    let renames = match relation {
+        Relation::Derived { columns, .. } => columns.as_slice(),
+        _ => &[],
+    };
+    let scope = Scope(
+        names
+            .iter()
+            .enumerate()
+            .map(|(i, c)| ScopeColumn {
+                qualifier: qualifier.clone(),
+                name: renames.get(i).unwrap_or(c).clone(),
+                hidden: false,
+            })
+            .collect(),
+    );
+    let columns = names
+        .iter()
+        .zip(scope.0.iter())
+        .map(|(nm, c)| col(nm).alias(&c.qualified()))
+        .collect::<Vec<_>>();
+    Ok((lf.select(columns), scope))
+}
+
+/// combine the results of two queries, columns are matched by position and
+/// named by the left query
+fn set_operation(op: SetOperator, all: bool, left: &Query, right: &Query, ctx: &Context) -> Result<LazyFrame, SqError> {
+    let name = format!("{:?}", op).to_uppercase();
+    let (lf, right) = (plan(left, ctx)?, plan(right, ctx)?);
+    let (lschema, rschema) = (lf.schema()?, right.schema()?);
+    if lschema.len() != rschema.len() {
+        return Err(SqError::SchemaError(format!(
+            "each {} query must have the same number of columns, got {} and {}", name, lschema.len(), rschema.len())));
+    }
+
+    let mut lcols = vec![];
+    let mut rcols = vec![];
+    for ((lname, ltype), (rname, rtype)) in lschema.iter().zip(rschema.iter()) {
+        let dtype = match (ltype, rtype) {
+            (l, r) if l == r => Some(l.clone()),
+            (DataType::Null, t) | (t, DataType::Null) => Some(t.clone()),
+            (l, r) if l.is_numeric() && r.is_numeric() => {
+                Some(if l.is_float() || r.is_float() { DataType::Float64 } else { DataType::Int64 })
+            }
+            _ => None,
+        };
+        let dtype = dtype.ok_or_else(|| SqError::SchemaError(format!(
+            "{} types {} of {} and {} of {} cannot be matched", name, ltype, lname, rtype, rname)))?;
+        lcols.push(col(lname).cast(dtype.clone()));
+        rcols.push(col(rname).cast(dtype).alias(lname));
+    }
+    let keys = lschema.iter_names().map(|nm| col(nm)).collect::<Vec<_>>();
+    let (lf, right) = (lf.select(lcols), right.select(rcols));
+
+    let how = match op {
+        SetOperator::Union if all => return Ok(concat([lf, right], false, true)?),
+        SetOperator::Union => return Ok(concat([lf, right], false, true)?.unique_stable(None, UniqueKeepStrategy::First)),
+        SetOperator::Intersect => JoinType::Semi,
+        SetOperator::Except => JoinType::Anti,
+    };
+    if !all {
+        return Ok(join_on(lf.unique_stable(None, UniqueKeepStrategy::First), right, keys.clone(), keys, how));
+    }
+
+    // with ALL, the n-th duplicate of a row only matches the n-th duplicate of it
+    // in the other side
+    let nth = |lf: LazyFrame| lf.with_column(keys[0].clone().cumcount(false).over(keys.clone()).alias("__sq_nth"));
+    let mut nth_keys = keys.clone();
+    nth_keys.push(col("__sq_nth"));
+    Ok(join_on(nth(lf), nth(right), nth_keys.clone(), nth_keys, how).drop_columns(["__sq_nth"]))
+}
+
+fn conjuncts(e: Expr, res: &mut Vec<Expr>) {
+    match e {
+        Expr::BinaryExpr { left, op: Operator::And, right } => {
+            conjuncts(*left, res);
+            conjuncts(*right, res);
+        }
+        e => res.push(e),
+    }
+}
+
+/// whether any of the columns `e` refers to comes from `scope`
+fn refers_to_any(e: &Expr, scope: &Scope) -> bool {
+    e.into_iter().any(|e| matches!(e, Expr::Column(name) if scope.contains(name)))
+}
+
+/// whether all the columns `e` refers to come from `scope`
+fn refers_to(e: &Expr, scope: &Scope) -> bool {
+    let mut columns = e.into_iter().filter_map(|e| match e {
+        Expr::Column(name) => Some(name),
+        _ => None,
+    }).peekable();
+    columns.peek().is_some() && columns.all(|c| scope.contains(c))
+}
+
+/// join on copies of the keys, so both key columns are kept by polars
+fn join_on(lf: LazyFrame, right: LazyFrame, left_on: Vec<Expr>, right_on: Vec<Expr>, how: JoinType) -> LazyFrame {
+    let lkeys = (0..left_on.len()).map(|i| format!("__sq_lkey_{}", i)).collect::<Vec<_>>();
+    let rkeys = (0..right_on.len()).map(|i| format!("__sq_rkey_{}", i)).collect::<Vec<_>>();
+    let lf = lf.with_columns(left_on.into_iter().zip(lkeys.iter()).map(|(e, k)| e.alias(k)).collect::<Vec<_>>());
+    let right = right.with_columns(right_on.into_iter().zip(rkeys.iter()).map(|(e, k)| e.alias(k)).collect::<Vec<_>>());
+    let lcols = lkeys.iter().map(|k| col(k)).collect::<Vec<_>>();
+    let rcols = rkeys.iter().map(|k| col(k)).collect::<Vec<_>>();
+
+    lf.join(right, lcols, rcols, how)
+        .drop_columns(lkeys.into_iter().chain(rkeys).collect::<Vec<_>>())
+}
+
+/// join on keys like SQL, where a NULL key matches nothing: polars matches NULL
+/// keys with each other, so rows with a NULL key are left out of the right side
+/// (and of the left side of an inner or semi join, where they would be dropped
+/// anyway), then for a full join added back unmatched
+fn join_not_null(lf: LazyFrame, right: LazyFrame, left_on: Vec<Expr>, right_on: Vec<Expr>, how: JoinType) -> Result<LazyFrame, SqError> {
+    let not_null = |keys: &[Expr]| keys.iter().map(|k| k.clone().is_not_null()).reduce(|a, b| a.and(b)).unwrap_or_else(|| lit(true));
+    let (lnot_null, rnot_null) = (not_null(&left_on), not_null(&right_on));
+    let lf = match how {
+        JoinType::Inner | JoinType::Semi => lf.filter(lnot_null),
+        _ => lf,
+    };
+    if how != JoinType::Outer {
+        return Ok(join_on(lf, right.filter(rnot_null), left_on, right_on, how));
+    }
+
+    let unmatched = join_on(lf.clone().filter(lit(false)), right.clone().filter(rnot_null.clone().not()),
+        left_on.clone(), right_on.clone(), JoinType::Outer);
+    let joined = join_on(lf, right.filter(rnot_null), left_on, right_on, JoinType::Outer);
+    Ok(concat([joined, unmatched], false, true)?)
+}
+
+/// join a relation onto `left`, equalities between both sides in the join
+/// condition become join keys, the rest are filters after an inner join
+fn join(
+    left: (LazyFrame, Scope),
+    join: &Join,
+    ctx: &Context,
+) -> Result<(LazyFrame, Scope), SqError> {
+    let (lf, mut scope) = left;
+    let (right, mut right_scope) = scan(&join.relation, ctx)?;
+    if let Some(c) = right_scope.0.first().filter(|c| scope.0.iter().any(|l| l.qualifier == c.qualifier)) {
+        return Err(SqError::AstError(format!("table name {} specified more than once", c.qualifier)));
+    }
+
+    if join.kind == JoinKind::Cross {
+        scope.0.extend(right_scope.0);
+        return Ok((lf.cross_join(right), scope));
+    }
+
+    let mut left_on = vec![];
+    let mut right_on = vec![];
+    let mut filters = vec![];
+    match &join.constraint {
+        JoinConstraint::On(e) => {
+            let both = Scope(scope.0.iter().chain(right_scope.0.iter()).cloned().collect());
+            let e = both.resolve_expr(e.clone())?;
+            both.check(&e, &[])?;
+            let mut preds = vec![];
+            conjuncts(e, &mut preds);
+            for pred in preds {
+                match &pred {
+                    Expr::BinaryExpr { left: l, op: Operator::Eq, right: r } => {
+                        if refers_to(l, &scope) && refers_to(r, &right_scope) {
+                            left_on.push(l.as_ref().clone());
+                            right_on.push(r.as_ref().clone());
+                        } else if refers_to(r, &scope) && refers_to(l, &right_scope) {
+                            left_on.push(r.as_ref().clone());
+                            right_on.push(l.as_ref().clone());
+                        } else {
+                            filters.push(pred);
+                        }
+                    }
+                    _ => filters.push(pred),
+                }
+            }
+        }
+        JoinConstraint::Using(columns) => {
+            for c in columns {
+                let l = scope.resolve(c)?;
+                let r = right_scope.resolve(c)?;
+                match (l, r) {
+                    (Some(l), Some(r)) => {
+                        left_on.push(col(&l));
+                        right_on.push(col(&r));
+                    }
+                    _ => return Err(SqError::AstError(format!("column {} in USING clause does not exist in both sides", c))),
+                }
+                right_scope.0.iter_mut().filter(|rc| rc.name == *c).for_each(|rc| rc.hidden = true);
+            }
+        }
+        JoinConstraint::None => {}
+    }
+
+    if left_on.is_empty() {
+        return Err(SqError::AstError("join condition should have an equality between both sides".to_owned()));
+    }
+    if !filters.is_empty() && join.kind != JoinKind::Inner {
+        return Err(SqError::AstError("only equality conditions are supported for outer, semi and anti join".to_owned()));
+    }
+
+    let lf = match join.kind {
+        JoinKind::Right => join_not_null(right, lf, right_on, left_on, JoinType::Left)?,
+        kind => {
+            let how = match kind {
+                JoinKind::Left => JoinType::Left,
+                JoinKind::Full => JoinType::Outer,
+                JoinKind::Semi => JoinType::Semi,
+                JoinKind::Anti => JoinType::Anti,
+                _ => JoinType::Inner,
+            };
+            join_not_null(lf, right, left_on, right_on, how)?
+        }
+    };
+    let lf = match filters.into_iter().reduce(|a, b| a.and(b)) {
+        Some(filter) => lf.filter(filter),
+        None => lf,
+    };
+
+    if !matches!(join.kind, JoinKind::Semi | JoinKind::Anti) {
+        scope.0.extend(right_scope.0);
+    }
+    Ok((lf, scope))
+}
+
+/// after projection, expressions (or sub-expressions) that are projected are
+/// referred to by their output column
+fn projected(mut e: Expr, projections: &[Expr]) -> Expr {
+    e.mutate().apply(|e| {
+        if let Some(p) = projections.iter().find(|p| unaliased(p) == e) {
+            *e = col(&output_name(p).unwrap_or_default());
+        }
+        true
+    });
+    e
+}
+
+/// before projection, output columns are referred to by their expressions
+fn unprojected(mut e: Expr, projections: &[Expr]) -> Expr {
+    e.mutate().apply(|e| {
+        if let Expr::Column(name) = e {
+            if let Some(p) = projections.iter().find(|p| output_name(p).as_ref() == Some(name)) {
+                *e = unaliased(p).clone();
+            }
+        }
+        true
+    });
+    e
+}
+
+/// filter `lf` by an IN or EXISTS subquery with a semi join (or anti join if
+/// negated), equalities in the subquery's WHERE correlated to the outer query
+/// become join keys
+fn semi_join(lf: LazyFrame, scope: &Scope, sub: &Subquery, ctx: &Context) -> Result<LazyFrame, SqError> {
+    let (expr, negated) = match &sub.kind {
+        SubqueryKind::In { expr, negated } => (Some(scope.resolve_expr(expr.clone())?), *negated),
+        SubqueryKind::Exists { negated } => (None, *negated),
+        SubqueryKind::Scalar => unreachable!(),
+    };
+    let how = if negated { JoinType::Anti } else { JoinType::Semi };
+    let query = &sub.query;
+
+    let (inner, inner_scope) = plan_from(query, ctx)?;
+    let mut preds = vec![];
+    if let Some(c) = &query.condition {
+        conjuncts(c.clone(), &mut preds);
+    }
+
+    let mut outer_keys = vec![];
+    let mut inner_keys = vec![];
+    let mut correlated = vec![];
+    let mut rest = vec![];
+    for pred in preds {
+        let pred = inner_scope.resolve_expr(pred)?;
+        let pred = scope.resolve_expr(pred)?;
+        if refers_to(&pred, &inner_scope) || !refers_to_any(&pred, scope) {
+            rest.push(pred);
+            continue;
+        }
+        match &pred {
+            Expr::BinaryExpr { left: l, op: Operator::Eq, right: r } if refers_to(l, scope) && refers_to(r, &inner_scope) => {
+                outer_keys.push(l.as_ref().clone());
+                inner_keys.push(r.as_ref().clone());
+            }
+            Expr::BinaryExpr { left: l, op: Operator::Eq, right: r } if refers_to(r, scope) && refers_to(l, &inner_scope) => {
+                outer_keys.push(r.as_ref().clone());
+                inner_keys.push(l.as_ref().clone());
+            }
+            _ => correlated.push(pred),
+        }
+    }
+
+    if outer_keys.is_empty() && correlated.is_empty() {
+        let inner = plan(query, ctx)?;
+        return match expr {
+            Some(expr) => {
+                let first = inner.schema()?.iter_names().next().cloned().unwrap_or_default();
+                if !negated {
+                    return join_not_null(lf, inner, vec![expr], vec![col(&first)], how);
+                }
+
+                // NOT IN is never true for a set with a NULL, nor for a NULL
+                // unless the set is empty
+                let values = inner.select([col(&first)]).collect()?;
+                let column = values.column(&first)?;
+                if column.null_count() > 0 {
+                    Ok(lf.filter(lit(false)))
+                } else if column.is_empty() {
+                    Ok(lf)
+                } else {
+                    join_not_null(lf.filter(expr.clone().is_not_null()), values.lazy(), vec![expr], vec![col(&first)], how)
+                }
+            }
+            None => {
+                let exists = inner.limit(1).collect()?.height() > 0;
+                Ok(lf.filter(lit(exists != negated)))
+            }
+        };
+    }
+
+    if query.is_aggregated() || query.limit.is_some() || query.offset.is_some() {
+        return Err(SqError::AstError("correlated subquery with aggregation or limit is not supported".to_owned()));
+    }
+    let inner = match rest.into_iter().reduce(|a, b| a.and(b)) {
+        Some(filter) => inner.filter(filter),
+        None => inner,
+    };
+    if let Some(expr) = expr {
+        let projection = query.projections.first().cloned().unwrap_or_default();
+        let value = inner_scope.resolve_expr(unaliased(&projection).clone())?;
+        if negated {
+            // a NULL on either side makes NOT IN not true, so it counts as a match
+            correlated.push(expr.eq(value).fill_null(lit(true)));
+        } else {
+            outer_keys.push(expr);
+            inner_keys.push(value);
+        }
+    }
+    let correlated = match correlated.into_iter().reduce(|a, b| a.and(b)) {
+        Some(correlated) => correlated,
+        None => return join_not_null(lf, inner, outer_keys, inner_keys, how),
+    };
+
+    // other correlated predicates are evaluated over the matched pairs, then rows
+    // of `lf` are filtered by their row numbers
+    let lf = lf.with_row_count("__sq_row", None);
+    let pairs = if outer_keys.is_empty() {
+        lf.clone().cross_join(inner)
+    } else {
+        join_not_null(lf.clone(), inner, outer_keys, inner_keys, JoinType::Inner)?
+    };
+    let matched = pairs.filter(correlated).select([col("__sq_row")]);
+    Ok(join_on(lf, matched, vec![col("__sq_row")], vec![col("__sq_row")], how).drop_columns(["__sq_row"]))
+}
+
+/// subqueries other than top level IN or EXISTS of WHERE are evaluated eagerly
+fn evaluate_subquery(sub: &Subquery, ctx: &Context) -> Result<Expr, SqError> {
+    let column = |df: DataFrame| match df.get_columns().as_slice() {
+        [s] => Ok(s.clone()),
+        _ => Err(SqError::AstError(format!("subquery must return only one column, got {}", df.width()))),
+    };
+
+    match &sub.kind {
+        SubqueryKind::Scalar => {
+            let s = column(plan(&sub.query, ctx)?.limit(2).collect()?)?;
+            match s.len() {
+                0 => Ok(lit(NULL)),
+                1 => Ok(Expr::Literal(LiteralValue::try_from(s.get(0))?)),
+                _ => Err(SqError::AstError("subquery used as an expression returns more than one row".to_owned())),
+            }
+        }
+        SubqueryKind::In { expr, negated } => {
+            // IN is false for an empty set, NULL for NULL, and NULL rather than
+            // false if the set has a NULL, the same for NOT IN negated
+            let s = column(plan(&sub.query, ctx)?.collect()?)?;
+            let e = if s.is_empty() {
+                lit(false)
+            } else {
+                let unmatched = if s.null_count() > 0 { lit(NULL) } else { lit(false) };
+                when(expr.clone().is_null())
+                    .then(lit(NULL))
+                    .when(expr.clone().is_in(lit(s.drop_nulls())))
+                    .then(lit(true))
+                    .otherwise(unmatched)
+            };
+            Ok(if *negated { e.not() } else { e })
+        }
+        SubqueryKind::Exists { negated } => {
+            let exists = plan(&sub.query, ctx)?.limit(1).collect()?.height() > 0;
+            Ok(lit(exists != *negated))
+        }
+    }
+}
+
+fn subquery_index(e: &Expr) -> Option<usize> {
+    match e {
+        Expr::Column(name) => name.strip_prefix(SUBQUERY_PLACEHOLDER).and_then(|i| i.parse().ok()),
+        _ => None,
+    }
+}
+
+/// name of a projection of a query without FROM, its alias like with FROM, or
+/// else the sql text of a literal, which polars would name `literal`
+fn constant_name(e: &Expr) -> String {
+    if let Some(name) = output_name(e) {
+        return name.to_string();
+    }
+    match e {
+        Expr::Literal(LiteralValue::Null) => "NULL".to_owned(),
+        Expr::Literal(LiteralValue::Boolean(b)) => b.to_string(),
+        Expr::Literal(LiteralValue::Int64(n)) => n.to_string(),
+        Expr::Literal(LiteralValue::Float64(n)) => n.to_string(),
+        Expr::Literal(LiteralValue::Utf8(s)) => format!("'{}'", s),
+        e => e.to_string(),
+    }
+}
+
+/// scan the FROM clause of a query
+fn plan_from(query: &Query, ctx: &Context) -> Result<(LazyFrame, Scope), SqError> {
+    match &query.source {
+        Some(source) => query
+            .joins
+            .iter()
+            .try_fold(scan(source, ctx)?, |ds, j| join(ds, j, ctx)),
+        // a query without FROM selects from a single row
+        None => Ok((df!("__sq_row" => [0u32])?.lazy(), Scope::default())),
+    }
+}
+
+/// plan the query of a CTE (or a view) with its columns renamed to the listed
+/// names
+pub(crate) fn plan_cte(cte: &Cte, ctx: &Context) -> Result<LazyFrame, SqError> {
+    let lf = plan(&cte.query, ctx)?;
+    if cte.columns.is_empty() {
+        return Ok(lf);
+    }
+
+    let names = lf.schema()?.iter_names().cloned().collect::<Vec<_>>();
+    if cte.columns.len() > names.len() {
+        return Err(SqError::AstError(format!(
+            "{} has {} columns available but {} columns specified", cte.name, names.len(), cte.columns.len())));
+    }
+    let renames = names.iter().enumerate().map(|(i, nm)| col(nm).alias(cte.columns.get(i).unwrap_or(nm)));
+    Ok(lf.select(renames.collect::<Vec<_>>()))
+}
+
+/// build the lazy frame of a query
+pub(crate) fn plan(query: &Query, ctx: &Context) -> Result<LazyFrame, SqError> {
+    // CTEs are planned once and cached, so are not re-evaluated when referred to
+    // multiple times
+    let mut with = None;
+    for cte in query.ctes.iter() {
+        let ctx = with.as_ref().unwrap_or(ctx);
+        let lf = plan_cte(cte, ctx)?;
+
+        let mut ctx = ctx.clone();
+        ctx.ctes.insert(cte.name.clone(), lf.cache());
+        with = Some(ctx);
+    }
+    let ctx = with.as_ref().unwrap_or(ctx);
+
+    let aggregated = query.is_aggregated();
+    let (ds, scope) = plan_from(query, ctx)?;
+
+    // IN and EXISTS at top level of WHERE filter by semi joins
+    let mut ds = ds;
+    let mut preds = vec![];
+    if let Some(c) = &query.condition {
+        conjuncts(c.clone(), &mut preds);
+    }
+    let mut condition = vec![];
+    let mut joined = vec![];
+    for pred in preds {
+        match subquery_index(&pred).map(|i| (i, &query.subqueries[i])) {
+            Some((i, sub)) if sub.kind != SubqueryKind::Scalar && query.source.is_some() => {
+                ds = semi_join(ds, &scope, sub, ctx)?;
+                joined.push(i);
+            }
+            _ => condition.push(pred),
+        }
+    }
+
+    let mut values = HashMap::new();
+    for (i, sub) in query.subqueries.iter().enumerate() {
+        if !joined.contains(&i) {
+            values.insert(subquery_placeholder(i), evaluate_subquery(sub, ctx)?);
+        }
+    }
+    let substitute = |mut e: Expr| {
+        e.mutate().apply(|e| {
+            if let Expr::Column(name) = e {
+                if let Some(v) = values.get(name.as_ref()) {
+                    *e = v.clone();
+                }
+            }
+            true
+        });
+        e
+    };
+
+    let replace = query.replace.iter().cloned().map(substitute).collect::<Vec<_>>();
+    let mut projections = vec![];
+    for e in query.projections.iter() {
+        projections.extend(scope.resolve_projection(substitute(e.clone()), &replace)?);
+    }
+    let condition = condition.into_iter().reduce(|a, b| a.and(b));
+    let condition = condition.map(|e| scope.resolve_expr(substitute(e))).transpose()?;
+    let group_by = query
+        .group_by
+        .iter()
+        .map(|e| scope.resolve_expr(substitute(e.clone())))
+        .collect::<Result<Vec<_>, _>>()?;
+    let having = query.having.clone().map(|e| scope.resolve_expr(substitute(e))).transpose()?;
+    for e in projections.iter() {
+        scope.check(e, &[])?;
+    }
+    for e in condition.iter().chain(&group_by).chain(&having) {
+        scope.check(e, &projections)?;
+    }
+
+    let ds = match condition {
+        Some(condition) => ds.filter(condition),
+        None => ds,
+    };
+
+    // window functions are evaluated over rows sorted by their ORDER BY, then the
+    // original order is restored
+    let ds = if query.windows.is_empty() {
+        ds
+    } else {
+        let mut ds = ds.with_row_count(functions::WINDOW_ROW, None);
+        for (i, w) in query.windows.iter().enumerate() {
+            if !w.order_by.is_empty() {
+                let mut by = vec![];
+                let mut desc = vec![];
+                for (e, d) in w.order_by.iter() {
+                    let e = scope.resolve_expr(substitute(e.clone()))?;
+                    scope.check(&e, &projections)?;
+                    by.push(e);
+                    desc.push(*d);
+                }
+                ds = ds.sort_by_exprs(by, desc, false);
+            }
+            let function = scope.resolve_expr(substitute(w.function.clone()))?;
+            let partition_by = w
+                .partition_by
+                .iter()
+                .map(|e| scope.resolve_expr(substitute(e.clone())))
+                .collect::<Result<Vec<_>, _>>()?;
+            for e in partition_by.iter().chain([&function]) {
+                scope.check(e, &projections)?;
+            }
+            let function = if partition_by.is_empty() { function } else { function.over(partition_by) };
+            ds = ds.with_column(function.alias(&window_placeholder(i)));
+        }
+        ds.sort(functions::WINDOW_ROW, Default::default()).drop_columns([functions::WINDOW_ROW])
+    };
+    // without aggregation rows are sorted before projection, so ORDER BY can refer
+    // to any column of the source, aggregated rows are sorted after aggregation
+    let mut order_by = vec![];
+    for o in query.order_by.iter() {
+        let e = match &o.expr {
+            Expr::Nth(i) => {
+                let p = projections.get(*i as usize).ok_or_else(|| {
+                    SqError::AstError(format!("ORDER BY position {} is not in select list", i + 1))
+                })?;
+                if aggregated {
+                    col(&output_name(p).unwrap_or_default())
+                } else {
+                    unaliased(p).clone()
+                }
+            }
+            e if aggregated => projected(scope.resolve_expr(substitute(e.clone()))?, &projections),
+            e => scope.resolve_expr(unprojected(substitute(e.clone()), &projections))?,
+        };
+        scope.check(&e, &projections)?;
+        // nulls are placed by sorting on whether the key is null before the key
+        if let Some(nulls_first) = o.nulls_first {
+            order_by.push((e.clone().is_null(), nulls_first));
+        }
+        order_by.push((e, o.desc));
+    }
+
+    // DISTINCT ON keeps the first row of each key, keys are computed as hidden
+    // columns along with the projections
+    let mut distinct_on = vec![];
+    for (i, e) in query.distinct.iter().flatten().enumerate() {
+        let e = substitute(e.clone());
+        let e = if aggregated {
+            projected(scope.resolve_expr(e)?, &projections)
+        } else {
+            scope.resolve_expr(unprojected(e, &projections))?
+        };
+        scope.check(&e, &projections)?;
+        distinct_on.push(e.alias(&format!("__sq_distinct_{}", i)));
+    }
+    let keys = distinct_on.iter().filter_map(output_name).map(|k| k.to_string()).collect::<Vec<_>>();
+
+    let ds = if aggregated {
+        let ds = aggregate(ds, group_by, projections, having, order_by);
+        if distinct_on.is_empty() { ds } else { ds.with_columns(distinct_on) }
+    } else {
+        let ds = if order_by.is_empty() {
+            ds
+        } else {
+            let (by, desc): (Vec<_>, Vec<_>) = order_by.into_iter().unzip();
+            ds.sort_by_exprs(by, desc, false)
+        };
+        let columns = projections.into_iter().chain(distinct_on).collect::<Vec<_>>();
+        if query.source.is_some() {
+            ds.select(columns)
+        } else {
+            // without FROM there's a single row, but polars selects a literal as
+            // one row even when WHERE filtered it out, so constants are added
+            // to the frame instead
+            let names = columns.iter().map(constant_name).collect::<Vec<_>>();
+            let columns = columns.into_iter().zip(names.iter()).map(|(e, nm)| match output_name(&e) {
+                Some(_) => e,
+                None => e.alias(nm),
+            });
+            ds.with_columns(columns.collect::<Vec<_>>()).select(names.iter().map(|nm| col(nm)).collect::<Vec<_>>())
+        }
+    };
+    let ds = match &query.distinct {
+        Some(_) if !keys.is_empty() => ds
+            .unique_stable(Some(keys.clone()), UniqueKeepStrategy::First)
+            .drop_columns(keys),
+        Some(_) => ds.unique_stable(None, UniqueKeepStrategy::First),
+        None => ds,
+    };
+    Ok(if query.offset.is_some() || query.limit.is_some() {
+        ds.slice(query.offset.unwrap_or(0), query.limit.unwrap_or(usize::MAX) as u32)
+    } else {
+        ds
+    })
+}
@@ -0,0 +1,60 @@
+use polars::prelude::*;
+
+use super::parser::{parse, Query};
+use super::{DataSet, Params, Session, SqError};
+
+/// a query parsed once and executed many times, sources are fetched again on
+/// every execution so the results follow the data
+#[derive(Debug, Clone)]
+pub struct PreparedQuery {
+    sql: String,
+    query: Query,
+    schema: Option<SchemaRef>,
+}
+
+impl PreparedQuery {
+    pub fn new<S: AsRef<str>>(sql: S) -> Result<Self, SqError> {
+        let sql = sql.as_ref().to_owned();
+        let query = parse(&sql)?;
+        Ok(PreparedQuery { sql, query, schema: None })
+    }
+
+    pub fn sql(&self) -> &str {
+        &self.sql
+    }
+
+    /// urls of the sources, a source that is a parameter is its placeholder
+    pub fn sources(&self) -> Vec<&str> {
+        self.query.sources()
+    }
+
+    /// placeholders in order of appearance, bare `?` are numbered like `?1`
+    pub fn params(&self) -> &[String] {
+        &self.query.params
+    }
+
+    /// schema of the result, known once the query has been validated or executed
+    pub fn schema(&self) -> Option<&Schema> {
+        self.schema.as_deref()
+    }
+
+    /// check the query against the schema of its sources without running it
+    pub async fn validate(&mut self, params: &Params) -> Result<SchemaRef, SqError> {
+        let lf = self.plan(params).await?;
+        Ok(lf.schema()?)
+    }
+
+    pub async fn execute(&mut self, params: &Params) -> Result<DataSet, SqError> {
+        let lf = self.plan(params).await?;
+        Ok(DataSet(lf.collect()?))
+    }
+
+    async fn plan(&mut self, params: &Params) -> Result<LazyFrame, SqError> {
+        let mut query = self.query.clone();
+        query.bind(params)?;
+
+        let lf = Session::new().plan(&query).await.map_err(|e| e.locate(&self.sql))?;
+        self.schema = Some(lf.schema()?);
+        Ok(lf)
+    }
+}
@@ -0,0 +1,359 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use polars::export::chrono::Utc;
+use polars::prelude::*;
+
+use super::fetch::{fetch, fetcher};
+use super::parser::{parse_script, Cte, Query, Statement};
+use super::plan::{plan, plan_cte, Context};
+use super::{load, save, DataSet, Diagnostic, Params, SqError};
+
+/// options of a session, changed by `SET name = value`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// rows read to infer the schema of a CSV source, all rows if 0
+    pub infer_schema_length: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options { infer_schema_length: 10 }
+    }
+}
+
+impl Options {
+    fn set(&mut self, name: &str, value: &str) -> Result<(), SqError> {
+        match name {
+            "infer_schema_length" => self.infer_schema_length = value.parse()?,
+            _ => return Err(SqError::unsupported(format!("SET {}", name))),
+        }
+        Ok(())
+    }
+}
+
+/// what a name registered in a session refers to
+#[derive(Debug)]
+pub enum TableSource {
+    /// fetched on first use, then kept in the session
+    Url(String),
+    DataSet(DataSet),
+}
+
+impl From<&str> for TableSource {
+    fn from(url: &str) -> Self {
+        TableSource::Url(url.to_owned())
+    }
+}
+
+impl From<String> for TableSource {
+    fn from(url: String) -> Self {
+        TableSource::Url(url)
+    }
+}
+
+impl From<DataSet> for TableSource {
+    fn from(ds: DataSet) -> Self {
+        TableSource::DataSet(ds)
+    }
+}
+
+impl From<DataFrame> for TableSource {
+    fn from(df: DataFrame) -> Self {
+        TableSource::DataSet(DataSet(df))
+    }
+}
+
+/// a catalog of named tables and views queried by name in FROM, along with the
+/// options of the session. statements of a script share a session, so views and
+/// tables created by a statement can be queried by the later ones
+#[derive(Debug, Default)]
+pub struct Session {
+    views: Vec<Cte>,
+    tables: HashMap<String, DataFrame>,
+    /// registered urls, loaded into `tables` once fetched
+    urls: HashMap<String, String>,
+    options: Options,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Session::default()
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    /// make `source` queryable as `name`, replacing what was registered or
+    /// created with the same name
+    pub fn register<T: Into<TableSource>>(&mut self, name: &str, source: T) {
+        self.deregister(name);
+        match source.into() {
+            TableSource::Url(url) => {
+                self.urls.insert(name.to_owned(), url);
+            }
+            TableSource::DataSet(ds) => {
+                self.tables.insert(name.to_owned(), ds.0);
+            }
+        }
+    }
+
+    /// forget the table or view named `name`, returns whether there was one
+    pub fn deregister(&mut self, name: &str) -> bool {
+        let len = self.views.len();
+        self.views.retain(|v| v.name != name);
+        let url = self.urls.remove(name).is_some();
+        let table = self.tables.remove(name).is_some();
+        url || table || self.views.len() != len
+    }
+
+    /// names of the tables and views of the session, sorted
+    pub fn tables(&self) -> Vec<&str> {
+        let mut res = self
+            .tables
+            .keys()
+            .chain(self.urls.keys())
+            .chain(self.views.iter().map(|v| &v.name))
+            .map(|nm| nm.as_str())
+            .collect::<Vec<_>>();
+        res.sort_unstable();
+        res.dedup();
+        res
+    }
+
+    pub async fn execute<S: AsRef<str>>(&mut self, sql: S) -> Result<DataSet, SqError> {
+        self.execute_with_params(sql, &Params::default()).await
+    }
+
+    /// run the statements of `sql` with `params` bound to their placeholders (see
+    /// [`crate::execute_with_params`]), and return the result of the last query,
+    /// empty if there's none
+    pub async fn execute_with_params<S: AsRef<str>>(&mut self, sql: S, params: &Params) -> Result<DataSet, SqError> {
+        let sql = sql.as_ref();
+        let mut res = DataSet(DataFrame::default());
+        for statement in parse_script(sql)? {
+            if let Some(ds) = self.run(statement, params).await.map_err(|e| e.locate(sql))? {
+                res = ds;
+            }
+        }
+        Ok(res)
+    }
+
+    /// run the `;` separated statements of `sql` in order, and return the result
+    /// of each query
+    pub async fn execute_script<S: AsRef<str>>(&mut self, sql: S) -> Result<Vec<DataSet>, SqError> {
+        let sql = sql.as_ref();
+        let mut res = vec![];
+        for statement in parse_script(sql)? {
+            res.extend(self.run(statement, &Params::default()).await.map_err(|e| e.locate(sql))?);
+        }
+        Ok(res)
+    }
+
+    async fn run(&mut self, statement: Statement, params: &Params) -> Result<Option<DataSet>, SqError> {
+        match statement {
+            Statement::Query(mut query) => {
+                query.bind(params)?;
+                return Ok(Some(DataSet(self.plan(&query).await?.collect()?)));
+            }
+            Statement::CreateView { mut view, or_replace } => {
+                view.query.bind(params)?;
+                // the view replaced is left out while checking the new one, so that
+                // it can't refer to the old one, and put back if the check fails
+                let old = match self.views.iter().position(|v| v.name == view.name) {
+                    Some(i) if or_replace => Some((i, self.views.remove(i))),
+                    _ => None,
+                };
+                // planned once to check it, the sources it reads from are fetched
+                // again whenever it's queried
+                let checked = match self.check_name(&view.name) {
+                    Ok(()) => self.plan(&view.query).await.and_then(|lf| Ok(lf.schema().map(|_| ())?)),
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = checked {
+                    if let Some((i, old)) = old {
+                        self.views.insert(i, old);
+                    }
+                    return Err(e);
+                }
+                self.views.push(view);
+            }
+            Statement::CreateTable { mut table, or_replace, if_not_exists } => {
+                if if_not_exists && self.tables().contains(&table.name.as_str()) {
+                    return Ok(None);
+                }
+                table.query.bind(params)?;
+                let df = self.plan(&table.query).await?.collect()?;
+                if or_replace {
+                    self.deregister(&table.name);
+                }
+                self.check_name(&table.name)?;
+                self.tables.insert(table.name, df);
+            }
+            Statement::Drop { view, names, if_exists } => {
+                for name in names.iter() {
+                    let found = if view {
+                        self.views.iter().any(|v| v.name == *name)
+                    } else {
+                        self.tables.contains_key(name) || self.urls.contains_key(name)
+                    };
+                    if !found && !if_exists {
+                        let kind = if view { "view" } else { "table" };
+                        return Err(SqError::AstError(format!("{} {} does not exist", kind, name)));
+                    }
+                    let dependent = self.views.iter().find(|v| !names.contains(&v.name) && v.query.sources().contains(&name.as_str()));
+                    if let (true, Some(dependent)) = (found, dependent) {
+                        return Err(SqError::AstError(format!("cannot drop {} because view {} depends on it", name, dependent.name)));
+                    }
+                }
+                for name in names.iter() {
+                    if view {
+                        self.views.retain(|v| v.name != *name);
+                    } else {
+                        self.tables.remove(name);
+                        self.urls.remove(name);
+                    }
+                }
+            }
+            Statement::Set { name, value } => self.options.set(&name, &value)?,
+            Statement::Explain { mut query, analyze } => {
+                query.bind(params)?;
+                let mut trace = Trace::default();
+                let lf = self.plan_traced(&query, &mut trace).await?;
+                let mut rows = trace.sources.into_iter().map(|s| ("resolved".to_owned(), s)).collect::<Vec<_>>();
+                rows.extend(query.describe().into_iter().map(|(name, value)| (name.to_owned(), value)));
+                rows.push(("plan".to_owned(), lf.describe_optimized_plan()?));
+                if analyze {
+                    let start = Instant::now();
+                    let height = lf.collect()?.height();
+                    trace.timings.push(("execution".to_owned(), start.elapsed()));
+                    rows.extend(trace.timings.into_iter().map(|(stage, d)| (stage, format!("{:?}", d))));
+                    rows.push(("rows".to_owned(), height.to_string()));
+                }
+                let (names, values): (Vec<_>, Vec<_>) = rows.into_iter().unzip();
+                return Ok(Some(DataSet(df!("name" => names, "value" => values)?)));
+            }
+            Statement::Copy { mut query, target, options } => {
+                query.bind(params)?;
+                let mut ds = DataSet(self.plan(&query).await?.collect()?);
+                save(&target, &mut ds, &options, false)?;
+            }
+            Statement::Insert { target, mut query } => {
+                query.bind(params)?;
+                let mut ds = DataSet(self.plan(&query).await?.collect()?);
+                match self.tables.get_mut(&target) {
+                    Some(df) if !self.urls.contains_key(&target) => {
+                        if df.schema() != ds.schema() {
+                            return Err(SqError::SchemaError(format!("rows inserted into {} have a different schema", target)));
+                        }
+                        df.vstack_mut(&ds)?;
+                    }
+                    // a registered url is appended to, then fetched again when queried
+                    _ => match self.urls.get(&target) {
+                        Some(url) => {
+                            save(url, &mut ds, &[], true)?;
+                            self.tables.remove(&target);
+                        }
+                        None => save(&target, &mut ds, &[], true)?,
+                    },
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn check_name(&self, name: &str) -> Result<(), SqError> {
+        if self.tables().contains(&name) {
+            return Err(SqError::AstError(format!("relation {} already exists", name)));
+        }
+        Ok(())
+    }
+
+    /// build the lazy frame of a query, fetching the sources it reads from
+    /// directly or through views
+    pub(crate) async fn plan(&mut self, query: &Query) -> Result<LazyFrame, SqError> {
+        self.plan_traced(query, &mut Trace::default()).await
+    }
+
+    async fn fetch(&self, url: &str, trace: &mut Trace) -> Result<DataFrame, SqError> {
+        let start = Instant::now();
+        let data = fetch(url).await?;
+        trace.timings.push((format!("fetch {}", url), start.elapsed()));
+        let start = Instant::now();
+        let (ds, loader) = load(&data, &self.options)?;
+        trace.timings.push((format!("load {}", url), start.elapsed()));
+        trace.sources.push(format!("{} fetched by {}, loaded by {}", url, fetcher(url)?.name(), loader));
+        Ok(ds.0)
+    }
+
+    async fn plan_traced(&mut self, query: &Query, trace: &mut Trace) -> Result<LazyFrame, SqError> {
+        let mut sources = vec![];
+        let mut used = vec![];
+        let mut pending = query.sources().into_iter().map(str::to_owned).collect::<Vec<_>>();
+        while let Some(name) = pending.pop() {
+            if self.tables.contains_key(&name) {
+                trace.sources.push(format!("{}, a table of the session", name));
+                continue;
+            }
+            if let Some(url) = self.urls.get(&name) {
+                let df = self.fetch(url, trace).await?;
+                self.tables.insert(name, df);
+                continue;
+            }
+            match self.views.iter().find(|v| v.name == name) {
+                Some(view) if !used.contains(&name) => {
+                    trace.sources.push(format!("{}, a view of the session", name));
+                    pending.extend(view.query.sources().into_iter().map(str::to_owned));
+                    used.push(name);
+                }
+                Some(_) => {}
+                // a name without a scheme can only be a relation of the session
+                None if !name.contains(':') => {
+                    let candidates = self.tables().into_iter().map(str::to_owned).collect::<Vec<_>>();
+                    return Err(SqError::Diagnostic(Box::new(Diagnostic::relation_not_found(&name, candidates))));
+                }
+                None if !sources.contains(&name) => sources.push(name),
+                None => {}
+            }
+        }
+
+        let mut tables = HashMap::new();
+        for source in sources {
+            let df = self.fetch(&source, trace).await?;
+            tables.insert(source, df);
+        }
+
+        let start = Instant::now();
+        // the time is the same for the whole query, including the views it reads from
+        let now = Utc::now().naive_utc();
+        let mut ctx = Context { tables: &tables, ctes: HashMap::new() };
+        for (name, df) in self.tables.iter() {
+            ctx.ctes.insert(name.clone(), df.clone().lazy());
+        }
+        // views are planned after the views they refer to
+        let mut unplanned = self.views.iter().filter(|v| used.contains(&v.name)).collect::<Vec<_>>();
+        while !unplanned.is_empty() {
+            let ready = unplanned
+                .iter()
+                .position(|v| v.query.sources().iter().all(|s| !unplanned.iter().any(|u| u.name == *s)))
+                .ok_or_else(|| SqError::AstError(format!("view {} refers to itself", unplanned[0].name)))?;
+            let mut view = unplanned.remove(ready).clone();
+            view.query.bind_now(now)?;
+            let lf = plan_cte(&view, &ctx)?;
+            ctx.ctes.insert(view.name, lf);
+        }
+        let mut query = query.clone();
+        query.bind_now(now)?;
+        let lf = plan(&query, &ctx)?;
+        trace.timings.push(("planning".to_owned(), start.elapsed()));
+        Ok(lf)
+    }
+}
+
+/// what planning a query did, reported by EXPLAIN
+#[derive(Debug, Default)]
+struct Trace {
+    sources: Vec<String>,
+    timings: Vec<(String, Duration)>,
+}